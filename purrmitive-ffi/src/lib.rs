@@ -4,24 +4,50 @@ use purrmitive::*;
 
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::sync::Once;
 
 use env_logger::Builder;
 use log::{error, info, LevelFilter};
 use once_cell::sync::OnceCell;
 
-static mut RUNNER: OnceCell<Box<dyn PurrModelRunner<M = PurrHillClimbModel> + Send + Sync>> =
+static mut RUNNER: OnceCell<Box<dyn PurrModelRunner<M = PurrSearchModel> + Send + Sync>> =
     OnceCell::new();
 
-static mut MODEL: OnceCell<PurrHillClimbModel> = OnceCell::new();
+static mut MODEL: OnceCell<PurrSearchModel> = OnceCell::new();
 
+static LOGGER: Once = Once::new();
+
+// 0.2 breaks the abi of 0.1: struct_size was added in front of the fields, and the
+// fields after input are new. a caller sets struct_size to sizeof(PurrmitiveParam) of
+// its header, the fields past it are read as 0 or null, which is the default of each
+// of them, so that the callers built with an older header of 0.2 keep working
 #[repr(C)]
 pub struct PurrmitiveParam {
-    pub alpha: u8,
+    pub struct_size: u32,
+    pub alpha: u8, // 0 to choose alpha per shape
     pub mode: i32,
     pub resize: u32,
     pub size: u32,
-    pub bg: u32, // r, g, b, a
+    pub bg: u32, // 0xRRGGBBAA, 0 for the average color of the input
     pub input: *const c_char,
+    pub seed: u64,  // 0 for a random seed
+    pub model: i32, // 0 = hill climbing, 1 = simulated annealing
+    pub temperature_start: f64,
+    pub temperature_end: f64,
+    pub temperature_steps: u32, // 0 for the default schedule
+    pub n: u32,                 // random candidates of each climb, 0 for 1000
+    pub m: u32,                 // number of climbs, 0 for 16
+    pub age: u32,               // max age of a hill climb, 0 for 100
+    pub weights: *const c_char, // grayscale weight image, null for none
+    pub focus: i32,             // 0 = none, 1 = edges, 2 = variance, ignored with weights
+    pub metric: i32,            // 0 = rgb, 1 = CIELAB, 2 = OKLab
+    pub objective: i32,         // 0 = rms, 1 = 1 - ssim
+    pub palette: *const c_char, // hex colors separated by commas, or a palette file, null for none
+    pub colors: u32, // extract a palette of this many colors, 0 for none, ignored with palette
+    pub residual: i32, // 1 to place random shapes where the error is large
+    pub transparent: i32, // 1 to keep the transparent areas of the input transparent
+    pub blend: i32,  // 0 = srgb, 1 = linear light
+    pub antialias: i32, // 1 to score the shapes with partly covered edge pixels
 }
 
 #[repr(C)]
@@ -40,42 +66,235 @@ pub struct PurrmitiveContextInfo {
     pub score: f64,
 }
 
-fn create_cb<T: PurrShape + std::fmt::Debug>() -> Box<dyn FnMut(usize, PurrState<T>) + Send + Sync>
-{
-    Box::new(move |step, x| {
+fn create_cb<T: PurrShape + std::fmt::Debug>() -> OnStep<T> {
+    Box::new(move |step, x, _| {
         info!("step {}: {:?}", step, x);
     })
 }
 
+// install the logger once, it logs the errors until purrmitive_set_verbose sets the
+// level. the logger passes every message, the level is the max level of log
+fn init_logger() {
+    LOGGER.call_once(|| {
+        let mut logger_builder = Builder::new();
+        logger_builder.filter_level(LevelFilter::Trace);
+        // the host may have a logger already
+        if logger_builder.try_init().is_ok() {
+            log::set_max_level(LevelFilter::Error);
+        }
+    });
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn purrmitive_set_verbose(verbose: i32) {
-    let mut logger_builder = Builder::new();
+    init_logger();
     let level = match verbose {
         0 => LevelFilter::Error,
         1 => LevelFilter::Info,
         2 => LevelFilter::Debug,
         3 | _ => LevelFilter::Trace,
     };
-    logger_builder.filter_level(level);
-    logger_builder.init();
+    log::set_max_level(level);
+}
+
+// the background of bg of PurrmitiveParam, None for the average color
+fn bg_color(bg: u32) -> Option<Rgba<u8>> {
+    match bg {
+        0 => None,
+        c => Some(Rgba(c.to_be_bytes())),
+    }
+}
+
+// a copy of the param of the caller, the fields past its struct_size are zeroed. None
+// if it does not even hold the fields up to input
+unsafe fn read_param(param: *const PurrmitiveParam) -> Option<PurrmitiveParam> {
+    if param.is_null() {
+        return None;
+    }
+    let size = (*param).struct_size as usize;
+    if size < std::mem::offset_of!(PurrmitiveParam, seed) {
+        return None;
+    }
+    // all the fields are numbers or pointers, for which zero is valid
+    let mut copy: PurrmitiveParam = std::mem::zeroed();
+    std::ptr::copy_nonoverlapping(
+        param as *const u8,
+        &mut copy as *mut PurrmitiveParam as *mut u8,
+        std::cmp::min(size, std::mem::size_of::<PurrmitiveParam>()),
+    );
+    Some(copy)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn purrmitive_init(param: *const PurrmitiveParam) {
+    init_logger();
     info!("purrmitive_init");
-    let input = CStr::from_ptr((*param).input)
-        .to_string_lossy()
-        .into_owned();
+    let param = match read_param(param) {
+        Some(p) => p,
+        None => {
+            error!("invalid param, struct_size should be sizeof(PurrmitiveParam)");
+            return;
+        }
+    };
+    let input = CStr::from_ptr(param.input).to_string_lossy().into_owned();
     info!("input: {}", input);
-    let ctx = PurrContext::new(input, (*param).resize, (*param).size, (*param).alpha, None);
+    let bg = bg_color(param.bg);
+    let ctx = PurrContext::new(input, param.resize, param.size, param.alpha, bg);
+    init_with_context(&param, ctx);
+}
+
+// like purrmitive_init, but the input is the w x h rgba pixels at data, row by row
+// without padding, the input of param is ignored
+#[no_mangle]
+pub unsafe extern "C" fn purrmitive_init_rgba(
+    param: *const PurrmitiveParam,
+    data: *const u8,
+    w: u32,
+    h: u32,
+) {
+    init_logger();
+    info!("purrmitive_init_rgba: {}x{}", w, h);
+    let param = match read_param(param) {
+        Some(p) => p,
+        None => {
+            error!("invalid param, struct_size should be sizeof(PurrmitiveParam)");
+            return;
+        }
+    };
+    if data.is_null() || w == 0 || h == 0 {
+        error!("invalid rgba image: {}x{}", w, h);
+        return;
+    }
+    let len = match (w as usize)
+        .checked_mul(h as usize)
+        .and_then(|n| n.checked_mul(4))
+    {
+        Some(len) => len,
+        None => {
+            error!("rgba image too large: {}x{}", w, h);
+            return;
+        }
+    };
+    let raw = std::slice::from_raw_parts(data, len).to_vec();
+    let img = match RgbaImage::from_raw(w, h, raw) {
+        Some(img) => img,
+        None => {
+            error!("invalid rgba image: {}x{}", w, h);
+            return;
+        }
+    };
+    let bg = bg_color(param.bg);
+    let ctx = PurrContext::from_rgba(img, param.resize, param.size, param.alpha, bg);
+    init_with_context(&param, Ok(ctx));
+}
+
+unsafe fn init_with_context(param: &PurrmitiveParam, ctx: PurrResult<PurrContext>) {
+    let seed = match param.seed {
+        0 => None,
+        s => Some(s),
+    };
+    let n = match param.n {
+        0 => 1000,
+        n => n,
+    };
+    let m = match param.m {
+        0 => 16,
+        m => m,
+    };
+    let age = match param.age {
+        0 => 100,
+        age => age,
+    };
+    // every thread climbs at least once, use less threads for small m
+    let thread_number = std::cmp::min(num_cpus::get() as u32, m);
+    if let Err(e) = check_search_params(n, m, thread_number) {
+        error!("{}", e);
+        return;
+    }
+    let metric = match param.metric {
+        1 => PurrColorSpace::Lab,
+        2 => PurrColorSpace::OkLab,
+        _ => PurrColorSpace::Rgb,
+    };
+    let mut ctx = match ctx {
+        Ok(ctx) => ctx.with_seed(seed).with_metric(metric),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if param.transparent != 0 {
+        ctx = ctx.with_transparency();
+    }
+    if param.antialias != 0 {
+        ctx = ctx.with_antialias();
+    }
+    if !param.weights.is_null() {
+        let path = CStr::from_ptr(param.weights).to_string_lossy().into_owned();
+        info!("weights: {}", path);
+        match WeightMap::open(path, ctx.w, ctx.h).and_then(|m| ctx.with_weights(Some(m))) {
+            Ok(c) => ctx = c,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    } else {
+        match param.focus {
+            1 => ctx = ctx.with_focus(PurrFocus::Edges),
+            2 => ctx = ctx.with_focus(PurrFocus::Variance),
+            _ => {}
+        }
+    }
+    if !param.palette.is_null() {
+        let palette = CStr::from_ptr(param.palette).to_string_lossy().into_owned();
+        info!("palette: {}", palette);
+        let palette = if std::path::Path::new(&palette).is_file() {
+            open_palette(palette)
+        } else {
+            parse_palette(&palette)
+        };
+        match palette {
+            Ok(p) => ctx = ctx.with_palette(p),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    } else if param.colors > 0 {
+        ctx = ctx.with_auto_palette(param.colors as usize);
+    }
+    let objective = match param.objective {
+        1 => PurrObjective::Ssim,
+        _ => PurrObjective::Rms,
+    };
+    let blend = match param.blend {
+        1 => Blend::linear(),
+        _ => Blend::srgb(),
+    };
+    ctx = ctx
+        .with_blend(blend)
+        .with_objective(objective)
+        .with_residual_sampling(param.residual != 0);
+    let model = match param.model {
+        1 => {
+            let schedule = match param.temperature_steps {
+                0 => AnnealingSchedule::default(),
+                steps => {
+                    AnnealingSchedule::new(param.temperature_start, param.temperature_end, steps)
+                }
+            };
+            PurrSearchModel::Annealing(PurrAnnealingModel::new(ctx, n, m, schedule))
+        }
+        _ => PurrSearchModel::HillClimb(PurrHillClimbModel::new(ctx, n, m, age)),
+    };
     match MODEL.get_mut() {
         Some(m) => {
             // reset
             info!("reset model");
-            m.reset(ctx, 1000, 16, 100);
+            *m = model;
         }
         None => {
-            let model = PurrHillClimbModel::new(ctx, 1000, 16, 100);
             match MODEL.set(model) {
                 Ok(()) => {}
                 Err(_) => error!("Failed to create model!"),
@@ -86,7 +305,13 @@ pub unsafe extern "C" fn purrmitive_init(param: *const PurrmitiveParam) {
     // take runner, reinit it
     RUNNER.take();
 
-    let mut runner = model_runner!((*param).mode, u32::MAX, num_cpus::get() as u32, create_cb);
+    let mut runner = match model_runner!(param.mode, u32::MAX, thread_number, create_cb) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
     runner.init(MODEL.get_mut().unwrap());
     match RUNNER.set(runner) {
         Ok(()) => {}
@@ -111,6 +336,48 @@ pub unsafe extern "C" fn purrmitive_step() {
     }
 }
 
+// climb the added shapes again with the given age (0 for 100), returns the number of
+// changed shapes
+#[no_mangle]
+pub unsafe extern "C" fn purrmitive_refine(age: u32) -> u32 {
+    let age = match age {
+        0 => 100,
+        age => age,
+    };
+    match RUNNER.get_mut() {
+        Some(r) => match MODEL.get_mut() {
+            Some(m) => r.refine(m, age) as u32,
+            None => {
+                error!("Failed to refine: Model not found!");
+                0
+            }
+        },
+        None => {
+            error!("Failed to refine: Runner not found!");
+            0
+        }
+    }
+}
+
+// remove the shapes which improve the score by less than threshold, the removals make
+// the score worse by less than threshold in total. returns the number of removed shapes
+#[no_mangle]
+pub unsafe extern "C" fn purrmitive_prune(threshold: f64) -> u32 {
+    match RUNNER.get_mut() {
+        Some(r) => match MODEL.get_mut() {
+            Some(m) => r.prune(m, threshold) as u32,
+            None => {
+                error!("Failed to prune: Model not found!");
+                0
+            }
+        },
+        None => {
+            error!("Failed to prune: Runner not found!");
+            0
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn purrmitive_stop() {
     match RUNNER.get_mut() {
@@ -127,10 +394,10 @@ pub unsafe extern "C" fn purrmitive_stop() {
 pub unsafe extern "C" fn purrmitive_get_bg() -> PurrmitiveColor {
     match MODEL.get() {
         Some(m) => PurrmitiveColor {
-            r: m.context.bg.0[0],
-            g: m.context.bg.0[1],
-            b: m.context.bg.0[2],
-            a: m.context.bg.0[3],
+            r: m.context().bg.0[0],
+            g: m.context().bg.0[1],
+            b: m.context().bg.0[2],
+            a: m.context().bg.0[3],
         },
         None => {
             error!("No bg color: Model is not found!");
@@ -148,10 +415,10 @@ pub unsafe extern "C" fn purrmitive_get_bg() -> PurrmitiveColor {
 pub unsafe extern "C" fn purrmitive_get_ctx_info() -> PurrmitiveContextInfo {
     match MODEL.get() {
         Some(m) => PurrmitiveContextInfo {
-            w: m.context.w,
-            h: m.context.h,
-            scale: m.context.scale,
-            score: m.context.score,
+            w: m.context().w,
+            h: m.context().h,
+            scale: m.context().scale,
+            score: m.context().score,
         },
         None => {
             error!("No context info: Model is not found!");
@@ -165,6 +432,44 @@ pub unsafe extern "C" fn purrmitive_get_ctx_info() -> PurrmitiveContextInfo {
     }
 }
 
+// copy at most len colors of the palette into colors, returns the size of the palette,
+// 0 if there is no palette
+#[no_mangle]
+pub unsafe extern "C" fn purrmitive_get_palette(colors: *mut PurrmitiveColor, len: u32) -> u32 {
+    match MODEL.get() {
+        Some(m) => match &m.context().palette {
+            Some(palette) => {
+                for (i, c) in palette.iter().take(len as usize).enumerate() {
+                    *colors.add(i) = PurrmitiveColor {
+                        r: c.0[0],
+                        g: c.0[1],
+                        b: c.0[2],
+                        a: c.0[3],
+                    };
+                }
+                palette.len() as u32
+            }
+            None => 0,
+        },
+        None => {
+            error!("No palette: Model is not found!");
+            0
+        }
+    }
+}
+
+// mean ssim of the current image against the input, 1 means identical
+#[no_mangle]
+pub unsafe extern "C" fn purrmitive_get_ssim() -> f64 {
+    match MODEL.get() {
+        Some(m) => m.context().ssim(),
+        None => {
+            error!("No ssim: Model is not found!");
+            0.0
+        }
+    }
+}
+
 #[no_mangle]
 // this function will return ownership of the C str, should be freed later
 pub unsafe extern "C" fn purrmitive_get_last_shape() -> *mut c_char {
@@ -186,3 +491,35 @@ pub extern "C" fn purrmitive_free_str(s: *mut c_char) {
         CString::from_raw(s)
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_param() {
+        let mut param: PurrmitiveParam = unsafe { std::mem::zeroed() };
+        param.alpha = 128;
+        param.age = 7;
+        param.antialias = 1;
+
+        // a caller which only knows the fields up to age
+        param.struct_size = std::mem::offset_of!(PurrmitiveParam, weights) as u32;
+        let read = unsafe { read_param(&param) }.unwrap();
+        assert_eq!((read.alpha, read.age, read.antialias), (128, 7, 0));
+
+        param.struct_size = std::mem::size_of::<PurrmitiveParam>() as u32;
+        let read = unsafe { read_param(&param) }.unwrap();
+        assert_eq!((read.alpha, read.age, read.antialias), (128, 7, 1));
+
+        param.struct_size = 4;
+        assert!(unsafe { read_param(&param) }.is_none());
+        assert!(unsafe { read_param(std::ptr::null()) }.is_none());
+    }
+
+    #[test]
+    fn test_bg_color() {
+        assert!(bg_color(0).is_none());
+        assert_eq!(bg_color(0x102030ff).unwrap().0, [0x10, 0x20, 0x30, 0xff]);
+    }
+}
@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use purrmitive::graphics::*;
+use rand::rngs::SmallRng;
+
+const W: u32 = 256;
+const H: u32 = 256;
+
+fn shapes<T: Shape>() -> Vec<T> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    (0..1000).map(|_| T::random(W, H, &mut rng)).collect()
+}
+
+// rasterize allocates the scanlines of every shape, rasterize_into reuses a buffer,
+// rasterize_coverage_into is the outline coverage of --antialias
+fn bench_shape<T: Shape>(c: &mut Criterion, name: &str) {
+    let shapes: Vec<T> = shapes();
+    let mut group = c.benchmark_group(name);
+    group.bench_function("rasterize", |b| {
+        b.iter(|| {
+            for s in &shapes {
+                black_box(s.rasterize(W, H));
+            }
+        })
+    });
+    group.bench_function("rasterize_into", |b| {
+        let mut lines = Vec::new();
+        b.iter(|| {
+            for s in &shapes {
+                s.rasterize_into(W, H, &mut lines);
+                black_box(&lines);
+            }
+        })
+    });
+    group.bench_function("rasterize_coverage_into", |b| {
+        let mut lines = Vec::new();
+        b.iter(|| {
+            for s in &shapes {
+                s.rasterize_coverage_into(W, H, &mut lines);
+                black_box(&lines);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_rasterize(c: &mut Criterion) {
+    bench_shape::<Triangle>(c, "triangle");
+    bench_shape::<Rectangle>(c, "rectangle");
+    bench_shape::<RotatedRectangle>(c, "rotated_rectangle");
+    bench_shape::<Ellipse>(c, "ellipse");
+    bench_shape::<Circle>(c, "circle");
+    bench_shape::<RotatedEllipse>(c, "rotated_ellipse");
+    bench_shape::<Quadratic>(c, "quadratic");
+    bench_shape::<Polygon>(c, "polygon");
+    bench_shape::<Combo>(c, "combo");
+}
+
+criterion_group!(benches, bench_rasterize);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use purrmitive::core::*;
+use purrmitive::graphics::*;
+use purrmitive::{Rgba, RgbaImage};
+use rand::rngs::SmallRng;
+
+const W: u32 = 256;
+const H: u32 = 256;
+
+// a synthetic input, so that the benches do not depend on the assets
+fn gradient(w: u32, h: u32) -> RgbaImage {
+    let mut img = RgbaImage::new(w, h);
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        p.0 = [x as u8, y as u8, ((x * y) >> 8) as u8, 255];
+    }
+    img
+}
+
+// the scanlines of the triangles, with the outline coverage of --antialias
+fn triangles(antialias: bool) -> Vec<Vec<Scanline>> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    (0..1000)
+        .map(|_| {
+            let t = Triangle::random(W, H, &mut rng);
+            let mut lines = Vec::new();
+            if antialias {
+                t.rasterize_coverage_into(W, H, &mut lines);
+            } else {
+                t.rasterize_into(W, H, &mut lines);
+            }
+            lines
+        })
+        .collect()
+}
+
+// the benches of the scanlines, and of the antialiased ones with a suffixed name
+fn with_antialias<F: FnMut(&str, Vec<Vec<Scanline>>)>(name: &str, mut f: F) {
+    f(name, triangles(false));
+    f(&format!("{}_antialias", name), triangles(true));
+}
+
+fn bench_compute_color(c: &mut Criterion) {
+    let origin = gradient(W, H);
+    let current = RgbaImage::from_pixel(W, H, Rgba([128, 128, 128, 255]));
+    with_antialias("compute_color", |name, shapes| {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                for lines in &shapes {
+                    black_box(compute_color(
+                        &origin,
+                        &current,
+                        lines,
+                        128,
+                        None,
+                        &Blend::srgb(),
+                    ));
+                }
+            })
+        });
+    });
+}
+
+fn bench_diff_partial(c: &mut Criterion) {
+    let origin = gradient(W, H);
+    let current = RgbaImage::from_pixel(W, H, Rgba([128, 128, 128, 255]));
+    let metric = PurrMetric::default();
+    let sse = diff_full(&origin, &current, None, &metric);
+    let color = Rgba([200, 100, 50, 128]);
+    with_antialias("diff_partial_with_color", |name, shapes| {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                for lines in &shapes {
+                    black_box(diff_partial_with_color(
+                        &origin,
+                        &current,
+                        lines,
+                        sse,
+                        color,
+                        None,
+                        &metric,
+                        &Blend::srgb(),
+                    ));
+                }
+            })
+        });
+    });
+}
+
+// one shape of the default search, with a fixed seed
+fn bench_hill_climb(c: &mut Criterion) {
+    let ctx = PurrContext::from_rgba(gradient(W, H), W, W, 128, None).with_seed(Some(1));
+    c.bench_function("best_hill_climb", |b| {
+        b.iter_batched(
+            || ctx.clone(),
+            |mut ctx| best_hill_climb::<Triangle>(&mut ctx, 100, 4, 100),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    let ctx = ctx.with_antialias();
+    c.bench_function("best_hill_climb_antialias", |b| {
+        b.iter_batched(
+            || ctx.clone(),
+            |mut ctx| best_hill_climb::<Triangle>(&mut ctx, 100, 4, 100),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_compute_color,
+    bench_diff_partial,
+    bench_hill_climb
+);
+criterion_main!(benches);
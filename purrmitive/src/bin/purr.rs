@@ -1,19 +1,49 @@
-use clap::{crate_version, App, Arg};
+use clap::{crate_version, App, Arg, ArgMatches};
 use purrmitive::core::*;
 use purrmitive::graphics::*;
 use purrmitive::*;
 
 use env_logger::Builder;
-use log::{error, info, LevelFilter};
+use log::{info, log_enabled, Level, LevelFilter};
+use std::str::FromStr;
 
-fn create_cb<T: PurrShape + std::fmt::Debug>() -> Box<dyn FnMut(usize, PurrState<T>) + Send + Sync>
-{
-    Box::new(move |step, x| {
-        info!("step: {}, score: {}", step, x.score);
+fn create_cb<T: PurrShape + std::fmt::Debug>() -> OnStep<T> {
+    Box::new(move |step, x, ctx| {
+        // ssim is computed over the whole image, skip it when it is not logged
+        if log_enabled!(Level::Info) {
+            info!("step: {}, score: {}, ssim: {}", step, x.score, ctx.ssim());
+        }
     })
 }
 
+fn invalid_arg(name: &str, value: &str) -> PurrError {
+    PurrError::InvalidParam(format!("invalid value for {}: {}", name, value))
+}
+
+// the value of the option, or the default when it is not given
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, default: &str) -> PurrResult<T> {
+    let value = matches.value_of(name).unwrap_or(default);
+    value.parse().map_err(|_| invalid_arg(name, value))
+}
+
+fn parse_opt<T: FromStr>(matches: &ArgMatches, name: &str) -> PurrResult<Option<T>> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid_arg(name, value)),
+        None => Ok(None),
+    }
+}
+
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> PurrResult<()> {
     let matches = App::new("Purr")
         .version(crate_version!())
         .author("Yongsheng Xu")
@@ -73,7 +103,7 @@ fn main() {
         .arg(
             Arg::with_name("alpha")
                 .short("a")
-                .help("alpha value")
+                .help("alpha value, 0 lets the algorithm choose alpha per shape")
                 .takes_value(true),
         )
         .arg(
@@ -88,26 +118,213 @@ fn main() {
                 .help("starting background color (hex)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .help("random seed, runs with the same seed and thread number are reproducible")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("candidates")
+                .long("candidates")
+                .help("number of random candidates of each climb, default to 1000")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("climbs")
+                .long("climbs")
+                .help("number of climbs of each step, split among threads, default to 16")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("age")
+                .long("age")
+                .help("max age of a hill climb, default to 100")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("levels")
+                .long("levels")
+                .help("place half of the shapes at lower resolutions first, each level halves the resolution down to 16px, default to 1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tiles")
+                .long("tiles")
+                .help("split the image into tiles of the given size, each tile gets the number of shapes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("overlap")
+                .long("overlap")
+                .help("overlap of the tiles in pixels, default to 32")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seams")
+                .long("seams")
+                .help("number of shapes placed over the tile borders after stitching, default to 0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("refine")
+                .long("refine")
+                .help("number of passes climbing the added shapes again, with the same age, default to 0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .help("remove the shapes which improve the score by less than the threshold in total, like 0.0001")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("weights")
+                .long("weights")
+                .help("grayscale weight image, brighter pixels are reproduced in more detail")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("focus")
+                .long("focus")
+                .help("derive the weights from the input: edges=gradient magnitude variance=local variance")
+                .possible_values(&["edges", "variance"])
+                .conflicts_with("weights")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metric")
+                .long("metric")
+                .help("color space of the error: rgb, lab=CIELAB, oklab=OKLab(default rgb)")
+                .possible_values(&["rgb", "lab", "oklab"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("palette")
+                .long("palette")
+                .help("only use these colors: hex colors separated by commas, or a file with one hex color per line")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("colors")
+                .long("colors")
+                .help("only use the given number of main colors of the input, extracted by k-means")
+                .conflicts_with("palette")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("objective")
+                .long("objective")
+                .help("what to minimize: rms=root mean square error ssim=1-ssim(default rms)")
+                .possible_values(&["rms", "ssim"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("blend")
+                .long("blend")
+                .help("compositing of the shapes: srgb linear=linear light, png output is drawn without svg, svg viewers composite in srgb(default srgb)")
+                .possible_values(&["srgb", "linear"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("residual")
+                .long("residual")
+                .help("place random shapes where the error is large, instead of uniformly"),
+        )
+        .arg(
+            Arg::with_name("transparent")
+                .long("transparent")
+                .help("keep the transparent areas of the input transparent in the output"),
+        )
+        .arg(
+            Arg::with_name("antialias")
+                .long("antialias")
+                .help("score the shapes with partly covered edge pixels, like the svg output, slower"),
+        )
+        .arg(
+            Arg::with_name("model")
+                .long("model")
+                .help("search model: climb=hill climbing anneal=simulated annealing(default climb)")
+                .possible_values(&["climb", "anneal"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("temperature")
+                .long("temperature")
+                .help("annealing temperature schedule start:end:steps, default to 0.0001:0.000001:1000")
+                .takes_value(true),
+        )
         .get_matches();
     let mut logger_builder = Builder::new();
     let input = matches.value_of("input").unwrap();
     let output = matches.value_of("output").unwrap();
-    let shape_number = matches.value_of("number").unwrap_or("100").parse().unwrap();
-    let percision: f64 = matches
-        .value_of("percision")
-        .unwrap_or("0.0")
-        .parse()
-        .unwrap();
-    let shape = matches.value_of("mode").unwrap_or("1").parse().unwrap();
-    let thread_number = matches
-        .value_of("thread")
-        .unwrap_or(&num_cpus::get().to_string())
-        .parse()
-        .unwrap();
-    let input_size = matches.value_of("resize").unwrap_or("256").parse().unwrap();
-    let output_size = matches.value_of("size").unwrap_or("1024").parse().unwrap();
-    let alpha = matches.value_of("alpha").unwrap_or("128").parse().unwrap();
-    let bg = matches.value_of("background").unwrap_or("");
+    let shape_number = parse_arg(&matches, "number", "100")?;
+    let percision: f64 = parse_arg(&matches, "percision", "0.0")?;
+    let shape: i32 = parse_arg(&matches, "mode", "1")?;
+    let thread_number: u32 = parse_arg(&matches, "thread", &num_cpus::get().to_string())?;
+    let input_size = parse_arg(&matches, "resize", "256")?;
+    let output_size = parse_arg(&matches, "size", "1024")?;
+    let alpha = parse_arg(&matches, "alpha", "128")?;
+    let bg = matches
+        .value_of("background")
+        .map(parse_hex_color)
+        .transpose()?;
+    let seed = parse_opt(&matches, "seed")?;
+    let n = parse_arg(&matches, "candidates", "1000")?;
+    let m = parse_arg(&matches, "climbs", "16")?;
+    // every thread climbs at least once, use less threads for small m, unless both
+    // of them are given
+    let thread_number = if matches.is_present("thread") && matches.is_present("climbs") {
+        thread_number
+    } else {
+        std::cmp::min(thread_number, m)
+    };
+    let age = parse_arg(&matches, "age", "100")?;
+    let levels = parse_arg(&matches, "levels", "1")?;
+    let tiles: Option<u32> = parse_opt(&matches, "tiles")?;
+    let overlap = parse_arg(&matches, "overlap", "32")?;
+    let seams = parse_arg(&matches, "seams", "0")?;
+    let refine = parse_arg(&matches, "refine", "0")?;
+    let prune: Option<f64> = parse_opt(&matches, "prune")?;
+    let weights = matches.value_of("weights");
+    let focus = matches.value_of("focus").and_then(PurrFocus::parse);
+    let metric = matches
+        .value_of("metric")
+        .and_then(PurrColorSpace::parse)
+        .unwrap_or(PurrColorSpace::Rgb);
+    let objective = matches
+        .value_of("objective")
+        .and_then(PurrObjective::parse)
+        .unwrap_or(PurrObjective::Rms);
+    let palette = match matches.value_of("palette") {
+        Some(p) if std::path::Path::new(p).is_file() => Some(open_palette(p)?),
+        Some(p) => Some(parse_palette(p)?),
+        None => None,
+    };
+    let colors: Option<usize> = parse_opt(&matches, "colors")?;
+    let blend = matches
+        .value_of("blend")
+        .and_then(Blend::parse)
+        .unwrap_or_default();
+    let residual = matches.is_present("residual");
+    let transparent = matches.is_present("transparent");
+    let antialias = matches.is_present("antialias");
+    let model = matches.value_of("model").unwrap_or("climb");
+    let schedule = match matches.value_of("temperature") {
+        Some(t) => {
+            let parts: Vec<&str> = t.split(':').collect();
+            let invalid = || invalid_arg("temperature", t);
+            if parts.len() != 3 {
+                return Err(invalid());
+            }
+            AnnealingSchedule::new(
+                parts[0].parse().map_err(|_| invalid())?,
+                parts[1].parse().map_err(|_| invalid())?,
+                parts[2].parse().map_err(|_| invalid())?,
+            )
+        }
+        None => AnnealingSchedule::default(),
+    };
 
     let level = match matches.occurrences_of("v") {
         0 => LevelFilter::Error,
@@ -118,10 +335,99 @@ fn main() {
     logger_builder.filter_level(level);
     logger_builder.init();
 
-    let ctx = PurrContext::new(input, input_size, output_size, alpha, parse_hex_color(bg));
-    let mut model = PurrHillClimbModel::new(ctx, 1000, 16, 100);
-    let mut runner = model_runner!(shape, shape_number, thread_number, create_cb);
-    runner.run(&mut model, 1.0 - percision);
-    info!("done, now export to {}", output);
-    runner.save(&model.context, output);
+    check_search_params(n, m, thread_number)?;
+
+    if let Some(size) = tiles {
+        if size <= overlap {
+            return Err(PurrError::InvalidParam(
+                "tile size should be larger than the overlap".to_string(),
+            ));
+        }
+    }
+
+    let mut ctx = PurrContext::new(input, input_size, output_size, alpha, bg)?
+        .with_seed(seed)
+        .with_metric(metric);
+    if transparent {
+        ctx = ctx.with_transparency();
+    }
+    if antialias {
+        ctx = ctx.with_antialias();
+    }
+    if let Some(p) = palette {
+        if p.is_empty() {
+            return Err(PurrError::InvalidParam("empty palette".to_string()));
+        }
+        ctx = ctx.with_palette(p);
+    }
+    if let Some(k) = colors {
+        if k == 0 {
+            return Err(PurrError::InvalidParam(
+                "number of colors should be positive".to_string(),
+            ));
+        }
+        ctx = ctx.with_auto_palette(k);
+        if let Some(palette) = ctx.palette.as_ref() {
+            let hex: Vec<String> = palette
+                .iter()
+                .map(|c| format!("#{:02X}{:02X}{:02X}", c.0[0], c.0[1], c.0[2]))
+                .collect();
+            info!("palette: {}", hex.join(","));
+        }
+    }
+    if let Some(path) = weights {
+        let weight_map = WeightMap::open(path, ctx.w, ctx.h)?;
+        ctx = ctx.with_weights(Some(weight_map))?;
+    }
+    if let Some(f) = focus {
+        ctx = ctx.with_focus(f);
+    }
+    ctx = ctx
+        .with_blend(blend)
+        .with_objective(objective)
+        .with_residual_sampling(residual);
+    let mut model = match model {
+        "anneal" => PurrSearchModel::Annealing(PurrAnnealingModel::new(ctx, n, m, schedule)),
+        _ => PurrSearchModel::HillClimb(PurrHillClimbModel::new(ctx, n, m, age)),
+    };
+    let mut runner = match tiles {
+        Some(size) => tiled_model_runner!(
+            shape,
+            shape_number,
+            thread_number,
+            size,
+            overlap,
+            seams,
+            create_cb
+        ),
+        None => model_runner!(shape, shape_number, thread_number, create_cb),
+    }?;
+    runner.run_pyramid(&mut model, 1.0 - percision, levels);
+    for pass in 0..refine {
+        let changed = runner.refine(&mut model, age);
+        info!(
+            "refine pass {}: {} shapes changed, score: {}",
+            pass + 1,
+            changed,
+            model.context().score
+        );
+        if changed == 0 {
+            break;
+        }
+    }
+    if let Some(threshold) = prune {
+        let removed = runner.prune(&mut model, threshold);
+        info!(
+            "pruned {} shapes, score: {}",
+            removed,
+            model.context().score
+        );
+    }
+    info!(
+        "done, score: {}, ssim: {}, now export to {}",
+        model.context().score,
+        model.context().ssim(),
+        output
+    );
+    runner.save(model.context(), output)
 }
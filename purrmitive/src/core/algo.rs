@@ -1,37 +1,91 @@
 // algo implement the core algorithm
-use crate::graphics::Scanline;
-use crate::{alpha_compose, clamp};
+use crate::clamp;
+#[cfg(feature = "simd")]
+use crate::core::simd::{diff_rows_with_color, scalar_least_squares_rows};
+#[cfg(feature = "simd")]
+use crate::core::PurrColorSpace;
+use crate::core::{nearest_color, PurrError, PurrMetric, PurrResult, WeightMap};
+use crate::graphics::{has_partial_coverage, Blend, LinearTables, Scanline};
 use crate::{Rgba, RgbaImage};
 
-use log::error;
-
+// weighted by alpha, transparent pixels do not count
 pub fn average_color(img: &RgbaImage) -> Rgba<u8> {
-    let (w, h) = img.dimensions();
-    let mut r = 0;
-    let mut g = 0;
-    let mut b = 0;
-    for x in 0..w {
-        for y in 0..h {
-            let pixel: &Rgba<u8> = img.get_pixel(x as u32, y as u32);
-            let data = pixel.0;
-            r += data[0] as u32;
-            g += data[1] as u32;
-            b += data[2] as u32;
+    let mut sums = [0u64; 3];
+    let mut total = 0u64;
+    for p in img.pixels() {
+        let a = p.0[3] as u64;
+        for (s, c) in sums.iter_mut().zip(p.0.iter()) {
+            *s += *c as u64 * a;
+        }
+        total += a;
+    }
+    // all transparent, average the colors as they are
+    if total == 0 {
+        for p in img.pixels() {
+            for (s, c) in sums.iter_mut().zip(p.0.iter()) {
+                *s += *c as u64;
+            }
         }
+        total = img.pixels().len() as u64;
     }
-    r /= w * h;
-    g /= w * h;
-    b /= w * h;
+    let (r, g, b) = (sums[0] / total, sums[1] / total, sums[2] / total);
 
     Rgba([r as u8, g as u8, b as u8, 255])
 }
 
-pub fn compute_color(
+// the weight of a pixel, pixels are weighted equally without a weight map
+#[inline]
+fn weight_of(weights: Option<&WeightMap>, x: u32, y: u32) -> u32 {
+    match weights {
+        Some(m) => m.get(x, y),
+        None => 1,
+    }
+}
+
+// sum of the weights, which normalizes the squared error
+fn total_weight(img: &RgbaImage, weights: Option<&WeightMap>) -> u64 {
+    match weights {
+        Some(m) => m.total,
+        None => {
+            let (w, h) = img.dimensions();
+            (w * h) as u64
+        }
+    }
+}
+
+// the color which minimizes the squared error when composited onto current_img
+// with the given alpha, out of the rgb range if the error keeps decreasing there,
+// None when all the pixels are weighted zero
+fn least_squares_color(
     origin_img: &RgbaImage,
     current_img: &RgbaImage,
     lines: &Vec<Scanline>,
     alpha: u8,
-) -> Rgba<u8> {
+    weights: Option<&WeightMap>,
+    blend: &Blend,
+) -> Option<[i32; 3]> {
+    if has_partial_coverage(lines) {
+        return least_squares_coverage(origin_img, current_img, lines, alpha, weights, blend);
+    }
+    if let Some(t) = blend.tables() {
+        return least_squares_linear(origin_img, current_img, lines, alpha, weights, t);
+    }
+    #[cfg(feature = "simd")]
+    return scalar_least_squares_rows(origin_img, current_img, lines, alpha, weights);
+    #[cfg(not(feature = "simd"))]
+    least_squares_pixels(origin_img, current_img, lines, alpha, weights)
+}
+
+// the reference implementation of least_squares_color, only the tests use it with the
+// simd feature
+#[cfg_attr(feature = "simd", allow(dead_code))]
+fn least_squares_pixels(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+) -> Option<[i32; 3]> {
     let mut rsum = 0;
     let mut gsum = 0;
     let mut bsum = 0;
@@ -52,194 +106,262 @@ pub fn compute_color(
             let cr = data[0] as i32;
             let cg = data[1] as i32;
             let cb = data[2] as i32;
-            rsum += ((or - cr) * a + cr * 0x101) as i64;
-            gsum += ((og - cg) * a + cg * 0x101) as i64;
-            bsum += ((ob - cb) * a + cb * 0x101) as i64;
-            count += 1;
+            let wt = weight_of(weights, x, line.y) as i64;
+            rsum += ((or - cr) * a + cr * 0x101) as i64 * wt;
+            gsum += ((og - cg) * a + cg * 0x101) as i64 * wt;
+            bsum += ((ob - cb) * a + cb * 0x101) as i64 * wt;
+            count += wt;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    Some([
+        (rsum / count) as i32 >> 8,
+        (gsum / count) as i32 >> 8,
+        (bsum / count) as i32 >> 8,
+    ])
+}
+
+// like least_squares_color, the error is minimized in linear light, then the color is
+// clamped and converted back to srgb
+fn least_squares_linear(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+    t: &LinearTables,
+) -> Option<[i32; 3]> {
+    let mut sums = [0i64; 3];
+    let mut count = 0;
+    let a = alpha as i64;
+    for line in lines {
+        for x in line.x1..=line.x2 {
+            let o = origin_img.get_pixel(x, line.y).0;
+            let c = current_img.get_pixel(x, line.y).0;
+            let wt = weight_of(weights, x, line.y) as i64;
+            for ch in 0..3 {
+                let lo = t.to_linear[o[ch] as usize] as i64;
+                let lc = t.to_linear[c[ch] as usize] as i64;
+                sums[ch] += ((lo - lc) * 255 + lc * a) * wt;
+            }
+            count += wt;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    let srgb = |sum: i64| t.srgb((sum / count / a) as i32) as i32;
+    Some([srgb(sums[0]), srgb(sums[1]), srgb(sums[2])])
+}
+
+// like least_squares_color, the alpha of each pixel is scaled by the coverage of its
+// line. with alpha a_i the color f minimizing sum(w_i * (c_i + a_i * (f - c_i) - o_i)^2)
+// is sum(w_i * a_i * (o_i - c_i + a_i * c_i)) / sum(w_i * a_i^2)
+fn least_squares_coverage(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+    blend: &Blend,
+) -> Option<[i32; 3]> {
+    let t = blend.tables();
+    let value = |v: u8| match t {
+        Some(t) => t.to_linear[v as usize] as i64,
+        None => v as i64,
+    };
+    let mut sums = [0i64; 3];
+    let mut count = 0;
+    let color = Rgba([0, 0, 0, alpha]);
+    for line in lines {
+        let a = line.covered(&color).0[3] as i64;
+        for x in line.x1..=line.x2 {
+            let o = origin_img.get_pixel(x, line.y).0;
+            let c = current_img.get_pixel(x, line.y).0;
+            let wt = weight_of(weights, x, line.y) as i64 * a;
+            for ch in 0..3 {
+                let lo = value(o[ch]);
+                let lc = value(c[ch]);
+                sums[ch] += ((lo - lc) * 255 + lc * a) * wt;
+            }
+            count += wt * a;
         }
     }
-    assert_eq!(count > 0, true);
-    let r = clamp((rsum / count) as i32 >> 8, 0, 255) as u8;
-    let g = clamp((gsum / count) as i32 >> 8, 0, 255) as u8;
-    let b = clamp((bsum / count) as i32 >> 8, 0, 255) as u8;
-    Rgba([r, g, b, alpha])
+    if count == 0 {
+        return None;
+    }
+    let color = |sum: i64| match t {
+        Some(t) => t.srgb((sum / count) as i32) as i32,
+        None => (sum / count) as i32,
+    };
+    Some([color(sums[0]), color(sums[1]), color(sums[2])])
+}
+
+pub fn compute_color(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+    blend: &Blend,
+) -> Rgba<u8> {
+    match least_squares_color(origin_img, current_img, lines, alpha, weights, blend) {
+        Some(c) => Rgba([
+            clamp(c[0], 0, 255) as u8,
+            clamp(c[1], 0, 255) as u8,
+            clamp(c[2], 0, 255) as u8,
+            alpha,
+        ]),
+        // all pixels are weighted zero, color makes no difference to the score
+        None => Rgba([0, 0, 0, alpha]),
+    }
+}
+
+// like compute_color, but only the colors of the palette can be picked. the squared
+// rgb error of srgb compositing is a quadratic of the color with the same curvature in
+// r, g and b, so the palette color nearest to the unconstrained optimum has the least
+// error. it does not hold for the other metrics, objectives and blends, which score
+// each of the palette colors instead, see PurrContext::compute_color
+pub fn compute_palette_color(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+    palette: &[Rgba<u8>],
+    blend: &Blend,
+) -> Rgba<u8> {
+    let c = least_squares_color(origin_img, current_img, lines, alpha, weights, blend)
+        .unwrap_or([0; 3]);
+    let p = nearest_color(palette, c);
+    Rgba([p.0[0], p.0[1], p.0[2], alpha])
 }
 
+// the score, a normalized rms error in [0, 1], of the weighted sum of squared error
+pub fn score_of(origin_img: &RgbaImage, weights: Option<&WeightMap>, sse: u64) -> f64 {
+    (sse as f64 / (total_weight(origin_img, weights) * 4) as f64).sqrt() / 255.0
+}
+
+// the diff functions work on the exact sum of squared error, so that it never
+// drifts from the image over the steps, use score_of to get the score
+
 pub fn diff_partial(
     origin_img: &RgbaImage,
     before_img: &RgbaImage,
     after_img: &RgbaImage,
     lines: &Vec<Scanline>,
-    score: f64,
-) -> f64 {
-    let (w, h) = origin_img.dimensions();
-    let mut total: f64 = (score * 255.0) * (score * 255.0) * (w * h * 4) as f64;
+    sse: u64,
+    weights: Option<&WeightMap>,
+    metric: &PurrMetric,
+) -> u64 {
+    let mut total = sse;
 
     for line in lines {
         for x in line.x1..=line.x2 {
-            let mut pixel: &Rgba<u8> = origin_img.get_pixel(x as u32, line.y as u32);
-            let mut data = pixel.0;
-            let or = data[0] as i32;
-            let og = data[1] as i32;
-            let ob = data[2] as i32;
-            let oa = data[3] as i32;
-
-            pixel = before_img.get_pixel(x as u32, line.y as u32);
-            data = pixel.0;
-            let br = data[0] as i32;
-            let bg = data[1] as i32;
-            let bb = data[2] as i32;
-            let ba = data[3] as i32;
-
-            pixel = after_img.get_pixel(x as u32, line.y as u32);
-            data = pixel.0;
-            let ar = data[0] as i32;
-            let ag = data[1] as i32;
-            let ab = data[2] as i32;
-            let aa = data[3] as i32;
-
-            let dr1 = or - br;
-            let dg1 = og - bg;
-            let db1 = ob - bb;
-            let da1 = oa - ba;
-            let dr2 = or - ar;
-            let dg2 = og - ag;
-            let db2 = ob - ab;
-            let da2 = oa - aa;
-            total += (dr2 * dr2 + dg2 * dg2 + db2 * db2 + da2 * da2) as f64;
-            total -= (dr1 * dr1 + dg1 * dg1 + db1 * db1 + da1 * da1) as f64;
+            let origin = origin_img.get_pixel(x as u32, line.y as u32);
+            let before = before_img.get_pixel(x as u32, line.y as u32);
+            let after = after_img.get_pixel(x as u32, line.y as u32);
+            let wt = weight_of(weights, x, line.y) as u64;
+            // add first, total always contains the error of before
+            total += metric.error(origin, x, line.y, after) as u64 * wt;
+            total -= metric.error(origin, x, line.y, before) as u64 * wt;
         }
     }
-    (total / (w * h * 4) as f64).sqrt() / 255.0
+    total
 }
 
-pub fn diff_full(origin_img: &RgbaImage, current_img: &RgbaImage) -> f64 {
-    let mut total = 0.0;
+pub fn diff_full(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    weights: Option<&WeightMap>,
+    metric: &PurrMetric,
+) -> u64 {
+    let mut total = 0;
     let (w, h) = origin_img.dimensions();
     for x in 0..w {
         for y in 0..h {
-            let mut pixel: &Rgba<u8> = origin_img.get_pixel(x as u32, y as u32);
-            let mut data = pixel.0;
-            let or = data[0] as i32;
-            let og = data[1] as i32;
-            let ob = data[2] as i32;
-            let oa = data[3] as i32;
-
-            pixel = current_img.get_pixel(x as u32, y as u32);
-            data = pixel.0;
-
-            let cr = data[0] as i32;
-            let cg = data[1] as i32;
-            let cb = data[2] as i32;
-            let ca = data[3] as i32;
-
-            let dr = or - cr;
-            let dg = og - cg;
-            let db = ob - cb;
-            let da = oa - ca;
-            total += (dr * dr + dg * dg + db * db + da * da) as f64;
+            let origin = origin_img.get_pixel(x as u32, y as u32);
+            let current = current_img.get_pixel(x as u32, y as u32);
+            total += metric.error(origin, x, y, current) as u64 * weight_of(weights, x, y) as u64;
         }
     }
-    (total / (w * h * 4) as f64).sqrt() / 255.0
+    total
 }
 
+// the arguments are the ones of diff_partial plus the options of the context, they are
+// passed apart like in the other diff functions
+#[allow(clippy::too_many_arguments)]
 pub fn diff_partial_with_color(
     origin_img: &RgbaImage,
     before_img: &RgbaImage,
     lines: &Vec<Scanline>,
-    score: f64,
+    sse: u64,
     color: Rgba<u8>,
-) -> f64 {
-    if lines.is_empty() {
-        return score;
+    weights: Option<&WeightMap>,
+    metric: &PurrMetric,
+    blend: &Blend,
+) -> u64 {
+    #[cfg(feature = "simd")]
+    {
+        let srgb = blend.tables().is_none();
+        if metric.space == PurrColorSpace::Rgb && srgb && !has_partial_coverage(lines) {
+            return diff_rows_with_color(origin_img, before_img, lines, sse, color, weights);
+        }
     }
-    let (w, h) = origin_img.dimensions();
-    let mut total: u64 = ((score * 255.0) * (score * 255.0) * (w * h * 4) as f64) as u64;
+    diff_pixels_with_color(
+        origin_img, before_img, lines, sse, color, weights, metric, blend,
+    )
+}
+
+// the reference implementation of diff_partial_with_color
+#[allow(clippy::too_many_arguments)]
+fn diff_pixels_with_color(
+    origin_img: &RgbaImage,
+    before_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    sse: u64,
+    color: Rgba<u8>,
+    weights: Option<&WeightMap>,
+    metric: &PurrMetric,
+    blend: &Blend,
+) -> u64 {
+    let mut total = sse;
 
     for line in lines {
+        let color = line.covered(&color);
         for x in line.x1..=line.x2 {
-            let mut pixel: &Rgba<u8> = origin_img.get_pixel(x as u32, line.y as u32);
-            let mut data = pixel.0;
-            let or = data[0] as i32;
-            let og = data[1] as i32;
-            let ob = data[2] as i32;
-            let oa = data[3] as i32;
-
-            pixel = before_img.get_pixel(x as u32, line.y as u32);
-            let composed_color = alpha_compose(pixel, &color);
-            data = pixel.0;
-            let br = data[0] as i32;
-            let bg = data[1] as i32;
-            let bb = data[2] as i32;
-            let ba = data[3] as i32;
-
-            let ar = composed_color[0] as i32;
-            let ag = composed_color[1] as i32;
-            let ab = composed_color[2] as i32;
-            let aa = composed_color[3] as i32;
-
-            let dr1 = or - br;
-            let dg1 = og - bg;
-            let db1 = ob - bb;
-            let da1 = oa - ba;
-            let dr2 = or - ar;
-            let dg2 = og - ag;
-            let db2 = ob - ab;
-            let da2 = oa - aa;
-            total += (dr2 * dr2 + dg2 * dg2 + db2 * db2 + da2 * da2) as u64;
-            total -= (dr1 * dr1 + dg1 * dg1 + db1 * db1 + da1 * da1) as u64;
+            let origin = origin_img.get_pixel(x as u32, line.y as u32);
+            let before = before_img.get_pixel(x as u32, line.y as u32);
+            let after = blend.compose(before, &color);
+            let wt = weight_of(weights, x, line.y) as u64;
+            total += metric.error(origin, x, line.y, &after) as u64 * wt;
+            total -= metric.error(origin, x, line.y, before) as u64 * wt;
         }
     }
-    ((total as f64) / (w * h * 4) as f64).sqrt() / 255.0
+    total
 }
 
-pub fn parse_hex_color(hex: &str) -> Option<Rgba<u8>> {
+// #rgb, #rgba, #rrggbb or #rrggbbaa, the # is optional
+pub fn parse_hex_color(hex: &str) -> PurrResult<Rgba<u8>> {
     let h = hex.trim_start_matches("#");
-    let mut r = 0;
-    let mut g = 0;
-    let mut b = 0;
-    let mut a = 255;
-
-    match h.len() {
-        0 => {
-            return None;
-        }
-        3 => {
-            r = u8::from_str_radix(h.get(0..1).unwrap(), 16).unwrap();
-            r = r << 4 | r;
-            g = u8::from_str_radix(h.get(1..2).unwrap(), 16).unwrap();
-            g = g << 4 | g;
-            b = u8::from_str_radix(h.get(2..3).unwrap(), 16).unwrap();
-            b = b << 4 | b;
-        }
-        4 => {
-            r = u8::from_str_radix(h.get(0..1).unwrap(), 16).unwrap();
-            r = r << 4 | r;
-            g = u8::from_str_radix(h.get(1..2).unwrap(), 16).unwrap();
-            g = g << 4 | g;
-            b = u8::from_str_radix(h.get(2..3).unwrap(), 16).unwrap();
-            b = b << 4 | b;
-            a = u8::from_str_radix(h.get(3..4).unwrap(), 16).unwrap();
-            a = a << 4 | a;
-        }
-        6 => {
-            r = u8::from_str_radix(h.get(0..2).unwrap(), 16).unwrap();
-            g = u8::from_str_radix(h.get(2..4).unwrap(), 16).unwrap();
-            b = u8::from_str_radix(h.get(4..6).unwrap(), 16).unwrap();
-        }
-        8 => {
-            r = u8::from_str_radix(h.get(0..2).unwrap(), 16).unwrap();
-            g = u8::from_str_radix(h.get(2..4).unwrap(), 16).unwrap();
-            b = u8::from_str_radix(h.get(4..6).unwrap(), 16).unwrap();
-            a = u8::from_str_radix(h.get(6..8).unwrap(), 16).unwrap();
-        }
-        _ => {
-            error!("invalid hex color: {}", hex);
-            unreachable!();
-        }
-    }
-
-    Some(Rgba([r, g, b, a]))
+    let digits: Option<Vec<u8>> = h.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+    let d = digits.ok_or_else(|| PurrError::InvalidColor(hex.to_string()))?;
+    // a single digit is repeated, f is ff
+    let color = match d.len() {
+        3 => [d[0] * 17, d[1] * 17, d[2] * 17, 255],
+        4 => [d[0] * 17, d[1] * 17, d[2] * 17, d[3] * 17],
+        6 => [d[0] << 4 | d[1], d[2] << 4 | d[3], d[4] << 4 | d[5], 255],
+        8 => [
+            d[0] << 4 | d[1],
+            d[2] << 4 | d[3],
+            d[4] << 4 | d[5],
+            d[6] << 4 | d[7],
+        ],
+        _ => return Err(PurrError::InvalidColor(hex.to_string())),
+    };
+    Ok(Rgba(color))
 }
 
 #[cfg(test)]
@@ -262,11 +384,56 @@ mod tests {
                 y,
                 x1: 0,
                 x2: width - 1,
+                coverage: 255,
             });
         }
 
-        let c = compute_color(&img, &current_img, &lines, 255);
+        let c = compute_color(&img, &current_img, &lines, 255, None, &Blend::srgb());
+        assert_eq!(c, color);
+        let c = compute_color(&img, &current_img, &lines, 255, None, &Blend::linear());
         assert_eq!(c, color);
+
+        // half of the light of the red through alpha 128, onto black
+        let c = compute_color(&img, &current_img, &lines, 128, None, &Blend::linear());
+        assert_eq!(c, Rgba([255, 0, 0, 128]));
+        let half = Rgba([188, 0, 0, 255]);
+        let img = image::ImageBuffer::from_pixel(width, height, half);
+        let c = compute_color(&img, &current_img, &lines, 128, None, &Blend::linear());
+        assert_eq!(c, Rgba([255, 0, 0, 128]));
+    }
+
+    #[test]
+    fn test_compute_color_coverage() {
+        let red = Rgba([255, 0, 0, 255]);
+        let current_img = RgbaImage::new(100, 100);
+        let mut img = current_img.clone();
+        let mut lines = Vec::new();
+        // the lower half is covered by half, its red is composited with alpha 128
+        for y in 0..100 {
+            let line = Scanline {
+                y,
+                x1: 0,
+                x2: 99,
+                coverage: if y < 50 { 255 } else { 128 },
+            };
+            line.draw(&mut img, &red);
+            lines.push(line);
+        }
+        assert_eq!(img.get_pixel(0, 99).0, [128, 0, 0, 255]);
+
+        let c = compute_color(&img, &current_img, &lines, 255, None, &Blend::srgb());
+        assert_eq!(c, red);
+        let sse = diff_partial_with_color(
+            &img,
+            &current_img,
+            &lines,
+            diff_full(&img, &current_img, None, &PurrMetric::default()),
+            c,
+            None,
+            &PurrMetric::default(),
+            &Blend::srgb(),
+        );
+        assert_eq!(sse, 0);
     }
 
     #[test]
@@ -286,10 +453,19 @@ mod tests {
                 y,
                 x1: 0,
                 x2: width - 1,
+                coverage: 255,
             });
         }
-        let score = diff_partial(&img, &before_img, &before_img, &lines, 0.0);
-        assert_eq!(score, 0.0);
+        let score = diff_partial(
+            &img,
+            &before_img,
+            &before_img,
+            &lines,
+            0,
+            None,
+            &PurrMetric::default(),
+        );
+        assert_eq!(score, 0);
     }
 
     #[test]
@@ -309,12 +485,133 @@ mod tests {
                 y,
                 x1: 0,
                 x2: width - 1,
+                coverage: 255,
             });
         }
-        let score1 = diff_full(&img, &current_img);
-        let score2 = diff_full(&img, &img);
-        assert_eq!(score1 > 0.0, true);
-        assert_eq!(score2, 0.0);
+        let score1 = diff_full(&img, &current_img, None, &PurrMetric::default());
+        let score2 = diff_full(&img, &img, None, &PurrMetric::default());
+        assert_eq!(score1 > 0, true);
+        assert_eq!(score2, 0);
+    }
+
+    #[test]
+    fn test_diff_full_weighted() {
+        let width = 100;
+        let height = 100;
+        let mut img = RgbaImage::new(width, height);
+        let current_img = RgbaImage::new(width, height);
+        let mut weight_img = image::GrayImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                // left half differs, but it is weighted zero
+                if x < width / 2 {
+                    img.get_pixel_mut(x, y).0 = [255, 0, 0, 255];
+                } else {
+                    weight_img.get_pixel_mut(x, y).0 = [255];
+                }
+            }
+        }
+        let weights = WeightMap::new(&weight_img);
+        assert_eq!(
+            diff_full(&img, &current_img, Some(&weights), &PurrMetric::default()),
+            0
+        );
+        assert_eq!(
+            diff_full(&img, &current_img, None, &PurrMetric::default()) > 0,
+            true
+        );
+    }
+
+    #[test]
+    fn test_incremental_sse() {
+        use crate::core::{PurrContext, PurrHillClimbModel, PurrModel, PurrState};
+        use crate::graphics::Triangle;
+
+        let width = 64;
+        let height = 48;
+        let mut img = RgbaImage::new(width, height);
+        for (x, y, p) in img.enumerate_pixels_mut() {
+            p.0 = [(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255];
+        }
+        let ctx = PurrContext::from_rgba(img, 256, 256, 128, None).with_seed(Some(1));
+        let mut model = PurrHillClimbModel::new(ctx, 50, 2, 20);
+        for _ in 0..50 {
+            let state: PurrState<Triangle> = model.step();
+            model.add_state(&state);
+        }
+
+        let ctx = &model.context;
+        let cur = ctx.current_img.read().unwrap();
+        let sse = diff_full(&ctx.origin_img, &cur, None, &ctx.metric);
+        assert_eq!(ctx.sse, sse);
+        assert_eq!(ctx.score, score_of(&ctx.origin_img, None, sse));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn test_rows_match_pixels() {
+        use image::GrayImage;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        let width = 37;
+        let height = 23;
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut origin = RgbaImage::new(width, height);
+        let mut current = RgbaImage::new(width, height);
+        let mut gray = GrayImage::new(width, height);
+        for p in origin.pixels_mut() {
+            p.0 = rng.gen();
+        }
+        for p in current.pixels_mut() {
+            p.0 = rng.gen();
+        }
+        for p in gray.pixels_mut() {
+            p.0 = [rng.gen()];
+        }
+        let weights = WeightMap::new(&gray);
+        let metric = PurrMetric::default();
+
+        // every alpha, with rows of odd and even lengths for the simd tails
+        for alpha in 0..=255u8 {
+            let color = Rgba([rng.gen(), rng.gen(), rng.gen(), alpha]);
+            let x1 = rng.gen_range(0, width);
+            let lines = vec![
+                Scanline {
+                    y: rng.gen_range(0, height),
+                    x1,
+                    x2: rng.gen_range(x1, width),
+                    coverage: 255,
+                },
+                Scanline {
+                    y: 0,
+                    x1: 0,
+                    x2: width - 1,
+                    coverage: 255,
+                },
+            ];
+            for w in [None, Some(&weights)].iter() {
+                assert_eq!(
+                    diff_rows_with_color(&origin, &current, &lines, 1 << 40, color, *w),
+                    diff_pixels_with_color(
+                        &origin,
+                        &current,
+                        &lines,
+                        1 << 40,
+                        color,
+                        *w,
+                        &metric,
+                        &Blend::srgb()
+                    )
+                );
+                if alpha > 0 {
+                    assert_eq!(
+                        scalar_least_squares_rows(&origin, &current, &lines, alpha, *w),
+                        least_squares_pixels(&origin, &current, &lines, alpha, *w)
+                    );
+                }
+            }
+        }
     }
 
     #[test]
@@ -323,11 +620,22 @@ mod tests {
         let color2 = "#ffff";
         let color3 = "#ffffff";
         let color4 = "#ffffffff";
-        let result = Some(Rgba([255, 255, 255, 255]));
+        let result = Rgba([255, 255, 255, 255]);
+
+        assert_eq!(parse_hex_color(color1).unwrap(), result);
+        assert_eq!(parse_hex_color(color2).unwrap(), result);
+        assert_eq!(parse_hex_color(color3).unwrap(), result);
+        assert_eq!(parse_hex_color(color4).unwrap(), result);
+        assert_eq!(
+            parse_hex_color("#12345678").unwrap(),
+            Rgba([0x12, 0x34, 0x56, 0x78])
+        );
 
-        assert_eq!(parse_hex_color(color1), result);
-        assert_eq!(parse_hex_color(color2), result);
-        assert_eq!(parse_hex_color(color3), result);
-        assert_eq!(parse_hex_color(color4), result);
+        assert!(parse_hex_color("").is_err());
+        assert!(parse_hex_color("#ff").is_err());
+        assert!(parse_hex_color("#fffff").is_err());
+        assert!(parse_hex_color("#ggg").is_err());
+        assert!(parse_hex_color("#+ff").is_err());
+        assert!(parse_hex_color("#ffé").is_err());
     }
 }
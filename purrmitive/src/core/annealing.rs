@@ -0,0 +1,96 @@
+use crate::core::PurrShape;
+use crate::core::{best_random_step, mutate_state};
+use crate::core::{PurrContext, PurrState};
+use log::debug;
+use rand::Rng;
+
+// temperatures are in the unit of score, they cool down exponentially
+// from start to end within the given steps
+#[derive(Debug, Clone, Copy)]
+pub struct AnnealingSchedule {
+    pub start: f64,
+    pub end: f64,
+    pub steps: u32,
+}
+
+impl Default for AnnealingSchedule {
+    fn default() -> Self {
+        AnnealingSchedule {
+            start: 0.0001,
+            end: 0.000001,
+            steps: 1000,
+        }
+    }
+}
+
+impl AnnealingSchedule {
+    pub fn new(start: f64, end: f64, steps: u32) -> Self {
+        AnnealingSchedule { start, end, steps }
+    }
+
+    pub fn temperature(&self, step: u32) -> f64 {
+        if self.steps <= 1 {
+            return self.end;
+        }
+        let t = step as f64 / (self.steps - 1) as f64;
+        self.start * (self.end / self.start).powf(t)
+    }
+}
+
+pub fn best_anneal<T: PurrShape>(
+    ctx: &mut PurrContext,
+    n: u32,
+    m: u32,
+    schedule: &AnnealingSchedule,
+) -> PurrState<T> {
+    let mut best_state = PurrState::new(ctx.score, ctx.sse);
+    for i in 0..m {
+        let best_rand_state = best_random_step(ctx, n);
+        let anneal_state = anneal(ctx, best_rand_state, schedule);
+        debug!(
+            "anneal No.{}: {} -> {}",
+            i, best_rand_state.score, anneal_state.score
+        );
+        if best_state.score - anneal_state.score > 1e-6 {
+            best_state = anneal_state;
+        }
+    }
+
+    best_state
+}
+
+// like hill_climb, but a worse state is accepted with the probability of
+// exp(-delta / temperature), so the search can leave local optimums
+pub fn anneal<T: PurrShape>(
+    ctx: &mut PurrContext,
+    state: PurrState<T>,
+    schedule: &AnnealingSchedule,
+) -> PurrState<T> {
+    let mut cur_state = state;
+    let mut best_state = state;
+    for step in 0..schedule.steps {
+        let mut next_state = cur_state;
+        let alpha = mutate_state(ctx, &mut next_state);
+        ctx.rasterize_lines(&next_state.shape);
+        if ctx.lines.is_empty() {
+            continue;
+        }
+        {
+            let cur = ctx.current_img.read().unwrap();
+            next_state.color = ctx.compute_color(&cur, &ctx.lines, alpha);
+            let (score, sse) = ctx.partial_score(&cur, &ctx.lines, next_state.color);
+            next_state.score = score;
+            next_state.sse = sse;
+        }
+
+        let delta = next_state.score - cur_state.score;
+        let temperature = schedule.temperature(step);
+        if delta < 0.0 || ctx.rng.gen::<f64>() < (-delta / temperature).exp() {
+            cur_state = next_state;
+            if cur_state.score < best_state.score {
+                best_state = cur_state;
+            }
+        }
+    }
+    best_state
+}
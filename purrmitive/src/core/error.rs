@@ -0,0 +1,56 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum PurrError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Svg(nsvg::Error),
+    // the string is the color as given
+    InvalidColor(String),
+    UnsupportedMode(i32),
+    InvalidParam(String),
+}
+
+pub type PurrResult<T> = Result<T, PurrError>;
+
+impl fmt::Display for PurrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PurrError::Io(e) => write!(f, "io error: {}", e),
+            PurrError::Image(e) => write!(f, "image error: {}", e),
+            PurrError::Svg(e) => write!(f, "svg error: {}", e),
+            PurrError::InvalidColor(c) => write!(f, "invalid hex color: {}", c),
+            PurrError::UnsupportedMode(m) => write!(f, "unsupported mode {}", m),
+            PurrError::InvalidParam(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl std::error::Error for PurrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PurrError::Io(e) => Some(e),
+            PurrError::Image(e) => Some(e),
+            PurrError::Svg(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PurrError {
+    fn from(e: std::io::Error) -> Self {
+        PurrError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for PurrError {
+    fn from(e: image::ImageError) -> Self {
+        PurrError::Image(e)
+    }
+}
+
+impl From<nsvg::Error> for PurrError {
+    fn from(e: nsvg::Error) -> Self {
+        PurrError::Svg(e)
+    }
+}
@@ -1,6 +1,5 @@
 use crate::clamp;
 use crate::core::PurrShape;
-use crate::core::{compute_color, diff_partial_with_color};
 use crate::core::{PurrContext, PurrState};
 use log::debug;
 use rand::Rng;
@@ -11,7 +10,7 @@ pub fn best_hill_climb<T: PurrShape>(
     m: u32,
     age: u32,
 ) -> PurrState<T> {
-    let mut best_state = PurrState::new(ctx.score);
+    let mut best_state = PurrState::new(ctx.score, ctx.sse);
     for i in 0..m {
         let best_rand_state = best_random_step(ctx, n);
         let climb_state = hill_climb(ctx, best_rand_state, age);
@@ -46,18 +45,18 @@ pub fn hill_climb<T: PurrShape>(
             // cannot find any better state
             break;
         }
-        cur_state.shape.mutate(ctx.w, ctx.h, &mut ctx.rng);
-        let lines = cur_state.shape.rasterize(ctx.w, ctx.h);
-        let alpha = clamp(ctx.rng.gen_range(-10, 11) as i32 + ctx.alpha as i32, 1, 255);
-        if lines.is_empty() {
+        let alpha = mutate_state(ctx, &mut cur_state);
+        ctx.rasterize_lines(&cur_state.shape);
+        if ctx.lines.is_empty() {
             cur_state = best_state;
             continue;
         }
         {
             let cur = ctx.current_img.read().unwrap();
-            cur_state.color = compute_color(&ctx.origin_img, &cur, &lines, alpha as u8);
-            cur_state.score =
-                diff_partial_with_color(&ctx.origin_img, &cur, &lines, ctx.score, cur_state.color);
+            cur_state.color = ctx.compute_color(&cur, &ctx.lines, alpha);
+            let (score, sse) = ctx.partial_score(&cur, &ctx.lines, cur_state.color);
+            cur_state.score = score;
+            cur_state.sse = sse;
         }
 
         if best_state.score - cur_state.score > 1e-6 {
@@ -73,6 +72,22 @@ pub fn hill_climb<T: PurrShape>(
     best_state
 }
 
+// mutate the state, returns the alpha to compute the color with. alpha is jittered
+// around ctx.alpha, or with ctx.alpha 0 it is searched like the shape: either of them
+// is mutated, alpha with the probability of 1/3
+pub fn mutate_state<T: PurrShape>(ctx: &mut PurrContext, state: &mut PurrState<T>) -> u8 {
+    if ctx.alpha == 0 {
+        let alpha = state.color.0[3] as i32;
+        if ctx.rng.gen_range(0, 3) == 0 {
+            return clamp(alpha + ctx.rng.gen_range(-10, 11), 1, 255) as u8;
+        }
+        state.shape.mutate(ctx.w, ctx.h, &mut ctx.rng);
+        return alpha as u8;
+    }
+    state.shape.mutate(ctx.w, ctx.h, &mut ctx.rng);
+    clamp(ctx.rng.gen_range(-10, 11) as i32 + ctx.alpha as i32, 1, 255) as u8
+}
+
 pub fn best_random_step<T: PurrShape>(ctx: &mut PurrContext, n: u32) -> PurrState<T> {
     let mut best_state = PurrState::default();
     for _ in 0..n {
@@ -85,22 +100,56 @@ pub fn best_random_step<T: PurrShape>(ctx: &mut PurrContext, n: u32) -> PurrStat
 }
 pub fn random_step<T: PurrShape>(ctx: &mut PurrContext) -> PurrState<T> {
     // random generate triangle
-    let mut t = T::random(ctx.w, ctx.h, &mut ctx.rng);
-    let mut lines = Vec::new();
+    let mut t: T = ctx.random_shape();
     loop {
-        lines = t.rasterize(ctx.w, ctx.h);
-        if !lines.is_empty() {
+        ctx.rasterize_lines(&t);
+        if !ctx.lines.is_empty() {
             break;
         }
-        t = T::random(ctx.w, ctx.h, &mut ctx.rng);
+        t = ctx.random_shape();
     }
     let cur = ctx.current_img.read().unwrap();
-    let color = compute_color(&ctx.origin_img, &cur, &lines, ctx.alpha);
-    let score = diff_partial_with_color(&ctx.origin_img, &cur, &lines, ctx.score, color);
+    // searched alpha starts from the middle
+    let alpha = if ctx.alpha == 0 { 128 } else { ctx.alpha };
+    let color = ctx.compute_color(&cur, &ctx.lines, alpha);
+    let (score, sse) = ctx.partial_score(&cur, &ctx.lines, color);
 
     PurrState {
         shape: t,
         score,
+        sse,
         color,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Triangle;
+    use crate::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_searched_alpha() {
+        let img = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 0, 255])
+        });
+        let mut ctx = PurrContext::from_rgba(img, 16, 16, 0, None).with_seed(Some(3));
+        // the alpha stays within 1..=255 from both ends of the range
+        for &start in [1, 255].iter() {
+            let mut state: PurrState<Triangle> = PurrState::default();
+            state.color.0[3] = start;
+            let mut seen = std::collections::HashSet::new();
+            for _ in 0..1000 {
+                let alpha = mutate_state(&mut ctx, &mut state);
+                assert!(alpha >= 1);
+                seen.insert(alpha);
+                state.color.0[3] = alpha;
+            }
+            assert!(seen.len() > 1);
+        }
+
+        let state: PurrState<Triangle> = best_hill_climb(&mut ctx, 10, 2, 10);
+        assert!(state.color.0[3] >= 1);
+        assert!(state.score < ctx.score);
+    }
+}
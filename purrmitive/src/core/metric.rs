@@ -0,0 +1,210 @@
+use crate::{Rgba, RgbaImage};
+use std::sync::Arc;
+
+// the color space in which the error is measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PurrColorSpace {
+    Rgb,
+    // CIE L*a*b* (D65)
+    Lab,
+    OkLab,
+}
+
+impl PurrColorSpace {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rgb" => Some(PurrColorSpace::Rgb),
+            "lab" => Some(PurrColorSpace::Lab),
+            "oklab" => Some(PurrColorSpace::OkLab),
+            _ => None,
+        }
+    }
+}
+
+// error metric, pixels of the origin image are converted once,
+// so that only the candidate pixels are converted while scoring
+#[derive(Debug, Clone)]
+pub struct PurrMetric {
+    pub space: PurrColorSpace,
+    w: u32,
+    origin: Option<Arc<Vec<[i32; 4]>>>,
+    linear: Option<Arc<Vec<f32>>>,
+}
+
+impl Default for PurrMetric {
+    fn default() -> Self {
+        PurrMetric {
+            space: PurrColorSpace::Rgb,
+            w: 0,
+            origin: None,
+            linear: None,
+        }
+    }
+}
+
+impl PurrMetric {
+    pub fn new(space: PurrColorSpace, origin_img: &RgbaImage) -> Self {
+        if space == PurrColorSpace::Rgb {
+            return PurrMetric::default();
+        }
+        // srgb to linear table
+        let linear: Vec<f32> = (0..256)
+            .map(|i| {
+                let c = i as f32 / 255.0;
+                if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            })
+            .collect();
+        let mut metric = PurrMetric {
+            space,
+            w: origin_img.width(),
+            origin: None,
+            linear: Some(Arc::new(linear)),
+        };
+        let origin = origin_img.pixels().map(|p| metric.convert(p)).collect();
+        metric.origin = Some(Arc::new(origin));
+        metric
+    }
+
+    // pixel in the metric space, scaled to be comparable with 0-255 rgb values
+    #[inline]
+    pub fn convert(&self, p: &Rgba<u8>) -> [i32; 4] {
+        let data = p.0;
+        let linear = match &self.linear {
+            Some(l) => l,
+            None => {
+                return [
+                    data[0] as i32,
+                    data[1] as i32,
+                    data[2] as i32,
+                    data[3] as i32,
+                ]
+            }
+        };
+        let r = linear[data[0] as usize];
+        let g = linear[data[1] as usize];
+        let b = linear[data[2] as usize];
+        match self.space {
+            PurrColorSpace::Lab => {
+                let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+                let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+                let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+                let fx = lab_f(x);
+                let fy = lab_f(y);
+                let fz = lab_f(z);
+                [
+                    ((116.0 * fy - 16.0) * 2.55).round() as i32,
+                    (500.0 * (fx - fy)).round() as i32,
+                    (200.0 * (fy - fz)).round() as i32,
+                    data[3] as i32,
+                ]
+            }
+            _ => {
+                let l = fast_cbrt(0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b);
+                let m = fast_cbrt(0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b);
+                let s = fast_cbrt(0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b);
+                [
+                    ((0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s) * 255.0).round()
+                        as i32,
+                    ((1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s) * 255.0).round() as i32,
+                    ((0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s) * 255.0).round()
+                        as i32,
+                    data[3] as i32,
+                ]
+            }
+        }
+    }
+
+    // squared error between the origin pixel at (x, y) and p
+    #[inline]
+    pub fn error(&self, origin: &Rgba<u8>, x: u32, y: u32, p: &Rgba<u8>) -> u32 {
+        let (o, c) = match &self.origin {
+            Some(origin) => (origin[(y * self.w + x) as usize], self.convert(p)),
+            None => {
+                let o = origin.0;
+                let c = p.0;
+                let d0 = o[0] as i32 - c[0] as i32;
+                let d1 = o[1] as i32 - c[1] as i32;
+                let d2 = o[2] as i32 - c[2] as i32;
+                let d3 = o[3] as i32 - c[3] as i32;
+                return (d0 * d0 + d1 * d1 + d2 * d2 + d3 * d3) as u32;
+            }
+        };
+        let d0 = o[0] - c[0];
+        let d1 = o[1] - c[1];
+        let d2 = o[2] - c[2];
+        let d3 = o[3] - c[3];
+        (d0 * d0 + d1 * d1 + d2 * d2 + d3 * d3) as u32
+    }
+}
+
+// cube root of x in [0, 1], exponent trick for the first guess, then one halley iteration,
+// which is accurate enough for the 1/255 scaled output and much faster than cbrt
+#[inline]
+fn fast_cbrt(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let y = f32::from_bits(x.to_bits() / 3 + 709_921_077);
+    let y3 = y * y * y;
+    y * (y3 + 2.0 * x) / (2.0 * y3 + x)
+}
+
+fn lab_f(t: f32) -> f32 {
+    if t > 0.008_856 {
+        fast_cbrt(t)
+    } else {
+        7.787 * t + 16.0 / 116.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(c: [i32; 4], expected: [i32; 3]) {
+        for i in 0..3 {
+            assert!((c[i] - expected[i]).abs() <= 1, "{:?} != {:?}", c, expected);
+        }
+    }
+
+    #[test]
+    fn test_fast_cbrt() {
+        for i in 1..=1000 {
+            let x = i as f32 / 1000.0;
+            assert!((fast_cbrt(x) - x.cbrt()).abs() / x.cbrt() < 1e-4);
+        }
+        assert_eq!(fast_cbrt(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_convert() {
+        let img = RgbaImage::new(1, 1);
+        let white = Rgba([255, 255, 255, 255]);
+        let gray = Rgba([128, 128, 128, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+
+        // L* of the references is scaled by 2.55, a* and b* are kept
+        let lab = PurrMetric::new(PurrColorSpace::Lab, &img);
+        assert_near(lab.convert(&Rgba([0, 0, 0, 255])), [0, 0, 0]);
+        assert_near(lab.convert(&white), [255, 0, 0]);
+        // L* = 53.59
+        assert_near(lab.convert(&gray), [137, 0, 0]);
+        // 53.24, 80.09, 67.20
+        assert_near(lab.convert(&red), [136, 80, 67]);
+
+        // all of the components are scaled by 255
+        let oklab = PurrMetric::new(PurrColorSpace::OkLab, &img);
+        assert_near(oklab.convert(&white), [255, 0, 0]);
+        // L = 0.5999
+        assert_near(oklab.convert(&gray), [153, 0, 0]);
+        // 0.62796, 0.22486, 0.12585
+        assert_near(oklab.convert(&red), [160, 57, 32]);
+
+        let rgb = PurrMetric::new(PurrColorSpace::Rgb, &img);
+        assert_eq!(rgb.convert(&gray), [128, 128, 128, 255]);
+    }
+}
@@ -1,26 +1,47 @@
 pub mod algo;
+pub mod annealing;
+pub mod error;
 pub mod hill_climb;
+pub mod metric;
+pub mod palette;
+pub mod refine;
+pub mod sampler;
+#[cfg(feature = "simd")]
+mod simd;
+pub mod ssim;
+pub mod tile;
+pub mod weight;
 pub mod worker;
 
 pub use algo::*;
+pub use annealing::*;
+pub use error::*;
 pub use hill_climb::*;
+pub use metric::*;
+pub use palette::*;
+pub use refine::*;
+pub use sampler::*;
+pub use ssim::*;
+pub use tile::*;
+pub use weight::*;
 pub use worker::*;
 
 use crate::graphics::*;
-use crate::{Rgba, RgbaImage};
+use crate::{div255, GrayImage, Luma, Rgba, RgbaImage};
 use crossbeam_channel::bounded;
 use crossbeam_channel::{Receiver, Sender};
 use dyn_fmt::AsStrFormatExt;
 use gif::{Encoder, Frame, Repeat, SetParameter};
 use image::imageops::FilterType;
-use image::GenericImageView;
-use log::{debug, info};
+use image::{DynamicImage, GenericImageView};
+use log::{debug, info, warn};
 use nsvg;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 use std::ffi::OsStr;
-use std::fs::OpenOptions;
+use std::fs::File;
 use std::io::Write;
+use std::marker::PhantomData;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 use threadpool::ThreadPool;
@@ -32,12 +53,15 @@ pub struct PurrState<T> {
     pub shape: T,
     pub color: Rgba<u8>,
     pub score: f64,
+    // exact weighted sum of squared error of the image with this state drawn
+    pub sse: u64,
 }
 
 impl<T: PurrShape> Default for PurrState<T> {
     fn default() -> Self {
         PurrState {
             score: std::f64::MAX,
+            sse: u64::MAX,
             color: Rgba([0, 0, 0, 0]),
             shape: T::default(),
         }
@@ -45,21 +69,26 @@ impl<T: PurrShape> Default for PurrState<T> {
 }
 
 impl<T: PurrShape> PurrState<T> {
-    fn new(score: f64) -> Self {
+    fn new(score: f64, sse: u64) -> Self {
         PurrState {
             score,
+            sse,
             color: Rgba([0, 0, 0, 0]),
             shape: T::default(),
         }
     }
-    fn to_svg(&self) -> String {
-        let attr = format!(
+    // with a palette, the shape also gets the css class of its color
+    fn to_svg(&self, palette: Option<&[Rgba<u8>]>) -> String {
+        let mut attr = format!(
             "fill=\"#{:02X}{:02X}{:02X}\" fill-opacity=\"{}\"",
             self.color.0[0],
             self.color.0[1],
             self.color.0[2],
             self.color.0[3] as f64 / 255.0
         );
+        if let Some(i) = palette.and_then(|p| palette_index(p, &self.color)) {
+            attr = format!("class=\"c{}\" {}", i, attr);
+        }
         self.shape.to_svg(&attr)
     }
 }
@@ -72,9 +101,26 @@ pub struct PurrContext {
     pub origin_img: Arc<RgbaImage>,
     pub current_img: Arc<RwLock<RgbaImage>>,
     pub rng: SmallRng,
+    pub seed: Option<u64>,
     pub score: f64,
-    pub bg: Rgba<u8>, // TODO: heatmap pos
+    // exact weighted sum of squared error of current_img, the score of the rms objective
+    // is derived from it
+    pub sse: u64,
+    pub bg: Rgba<u8>,
     pub alpha: u8,
+    pub weights: Option<Arc<WeightMap>>,
+    pub metric: PurrMetric,
+    pub objective: PurrObjective,
+    pub palette: Option<Arc<Vec<Rgba<u8>>>>,
+    pub sampler: Option<Arc<RwLock<ResidualSampler>>>,
+    // alpha of the input, the output is cut out by it, see with_transparency
+    pub alpha_mask: Option<Arc<GrayImage>>,
+    pub blend: Blend,
+    // the edge pixels of the shapes are partly covered, like in the svg renderers
+    pub antialias: bool,
+    // scanlines of the shape being scored, reused by the search, each worker has
+    // its own context and so its own buffer
+    pub lines: Vec<Scanline>,
 }
 
 impl PurrContext {
@@ -84,8 +130,54 @@ impl PurrContext {
         output_size: u32,
         alpha: u8,
         bg: Option<Rgba<u8>>,
+    ) -> PurrResult<Self> {
+        let img = image::open(&input)?;
+        Ok(PurrContext::from_image(
+            img,
+            input_size,
+            output_size,
+            alpha,
+            bg,
+        ))
+    }
+
+    // an encoded image in any format supported by the image crate
+    pub fn from_bytes(
+        bytes: &[u8],
+        input_size: u32,
+        output_size: u32,
+        alpha: u8,
+        bg: Option<Rgba<u8>>,
+    ) -> PurrResult<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(PurrContext::from_image(
+            img,
+            input_size,
+            output_size,
+            alpha,
+            bg,
+        ))
+    }
+
+    pub fn from_rgba(
+        img: RgbaImage,
+        input_size: u32,
+        output_size: u32,
+        alpha: u8,
+        bg: Option<Rgba<u8>>,
+    ) -> Self {
+        let img = DynamicImage::ImageRgba8(img);
+        PurrContext::from_image(img, input_size, output_size, alpha, bg)
+    }
+
+    // the image is scaled down so that its larger side is at most input_size
+    pub fn from_image(
+        img: DynamicImage,
+        input_size: u32,
+        output_size: u32,
+        alpha: u8,
+        bg: Option<Rgba<u8>>,
     ) -> Self {
-        let img = image::open(&input).unwrap();
         let (width, height) = img.dimensions();
         let mut w;
         let mut h;
@@ -137,7 +229,8 @@ impl PurrContext {
             }
         }
 
-        let score = diff_full(&origin_img, &current_img);
+        let sse = diff_full(&origin_img, &current_img, None, &PurrMetric::default());
+        let score = score_of(&origin_img, None, sse);
         let scale = output_size as f32 / input_size as f32;
 
         PurrContext {
@@ -147,9 +240,417 @@ impl PurrContext {
             origin_img: Arc::new(origin_img),
             current_img: Arc::new(RwLock::new(current_img)),
             rng: SmallRng::from_entropy(),
+            seed: None,
             score,
+            sse,
             bg: color,
             alpha,
+            weights: None,
+            metric: PurrMetric::default(),
+            objective: PurrObjective::Rms,
+            palette: None,
+            sampler: None,
+            alpha_mask: None,
+            blend: Blend::srgb(),
+            antialias: false,
+            lines: Vec::new(),
+        }
+    }
+
+    // weight the error of each pixel, the map should be in the size of w x h
+    pub fn with_weights(self, weights: Option<WeightMap>) -> PurrResult<Self> {
+        if let Some(m) = &weights {
+            if (m.w, m.h) != (self.w, self.h) {
+                return Err(PurrError::InvalidParam(format!(
+                    "the weight map is {}x{}, it should be {}x{}",
+                    m.w, m.h, self.w, self.h
+                )));
+            }
+        }
+        Ok(self.set_weights(weights))
+    }
+
+    // with_weights for a map of the right size
+    fn set_weights(mut self, weights: Option<WeightMap>) -> Self {
+        // transparent pixels of the input are not scored
+        let weights = match &self.alpha_mask {
+            Some(mask) => Some(match weights {
+                Some(w) => w.masked(mask),
+                None => WeightMap::new(mask),
+            }),
+            None => weights,
+        };
+        self.weights = weights.map(Arc::new);
+        self.rescore();
+        self
+    }
+
+    // weight the pixels by the details of the input itself
+    pub fn with_focus(self, focus: PurrFocus) -> Self {
+        let weights = WeightMap::from_focus(&self.origin_img, focus);
+        self.set_weights(Some(weights))
+    }
+
+    // measure the error in a perceptual color space. only the scoring uses it, the
+    // color of each shape is still solved by least squares in rgb, which is close to
+    // but not always the color with the least perceptual error
+    pub fn with_metric(mut self, space: PurrColorSpace) -> Self {
+        self.metric = PurrMetric::new(space, &self.origin_img);
+        self.rescore();
+        self
+    }
+
+    // minimize 1 - ssim instead of the rms error, weights and metric only apply to rms
+    pub fn with_objective(mut self, objective: PurrObjective) -> Self {
+        self.objective = objective;
+        self.rescore();
+        self
+    }
+
+    // restrict the colors of the shapes and the background to the palette, it repaints
+    // the background, so it should be called before any shape is added
+    pub fn with_palette(mut self, palette: Vec<Rgba<u8>>) -> Self {
+        if palette.is_empty() {
+            self.palette = None;
+            return self;
+        }
+        // the nearest color to the average (or the given) background has the least error
+        let bg = nearest_color(
+            &palette,
+            [
+                self.bg.0[0] as i32,
+                self.bg.0[1] as i32,
+                self.bg.0[2] as i32,
+            ],
+        );
+        self.bg = Rgba([bg.0[0], bg.0[1], bg.0[2], 255]);
+        for pixel in self.current_img.write().unwrap().pixels_mut() {
+            *pixel = self.bg;
+        }
+        self.palette = Some(Arc::new(palette));
+        self.rescore();
+        self
+    }
+
+    // restrict the colors to k main colors of the input, see with_palette
+    pub fn with_auto_palette(self, k: usize) -> Self {
+        let palette = extract_palette(&self.origin_img, k);
+        self.with_palette(palette)
+    }
+
+    // the best color for the lines with the given alpha, onto cur which is the current image
+    pub fn compute_color(&self, cur: &RgbaImage, lines: &Vec<Scanline>, alpha: u8) -> Rgba<u8> {
+        let weights = self.weights.as_deref();
+        match &self.palette {
+            Some(p) if self.is_rgb_rms() => {
+                compute_palette_color(&self.origin_img, cur, lines, alpha, weights, p, &self.blend)
+            }
+            Some(p) => self.best_palette_color(cur, lines, alpha, p),
+            None => compute_color(&self.origin_img, cur, lines, alpha, weights, &self.blend),
+        }
+    }
+
+    // whether the score is the rms error of srgb composited rgb values, which the least
+    // squares colors minimize
+    fn is_rgb_rms(&self) -> bool {
+        self.metric.space == PurrColorSpace::Rgb
+            && self.objective == PurrObjective::Rms
+            && self.blend.tables().is_none()
+    }
+
+    // the palette color with the best partial score, for the scores which the nearest
+    // palette color to the least squares color does not minimize
+    fn best_palette_color(
+        &self,
+        cur: &RgbaImage,
+        lines: &Vec<Scanline>,
+        alpha: u8,
+        palette: &[Rgba<u8>],
+    ) -> Rgba<u8> {
+        let mut best = Rgba([palette[0].0[0], palette[0].0[1], palette[0].0[2], alpha]);
+        let mut best_score = f64::MAX;
+        for p in palette {
+            let color = Rgba([p.0[0], p.0[1], p.0[2], alpha]);
+            let (score, _) = self.partial_score(cur, lines, color);
+            if score < best_score {
+                best = color;
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    // how the shapes are composited, the colors are solved and the candidates scored
+    // with it, Blend::linear for linear light
+    pub fn with_blend(mut self, blend: Blend) -> Self {
+        self.blend = blend;
+        self.rescore();
+        self
+    }
+
+    // the colors are solved, the candidates scored and the shapes drawn with the
+    // coverage of the edge pixels, so that the search sees the edges like the output.
+    // the coverage is the exact area of the outline in each pixel, which makes the
+    // rasterization a few times slower
+    pub fn with_antialias(mut self) -> Self {
+        self.antialias = true;
+        self.rescore();
+        self
+    }
+
+    // the scanlines of the shape, with the coverage of the edges when antialiased
+    pub fn rasterize<T: PurrShape>(&self, shape: &T) -> Vec<Scanline> {
+        let mut lines = Vec::new();
+        rasterize_shape(shape, self.w, self.h, self.antialias, &mut lines);
+        lines
+    }
+
+    // like rasterize, into the lines buffer of the context
+    pub fn rasterize_lines<T: PurrShape>(&mut self, shape: &T) {
+        rasterize_shape(shape, self.w, self.h, self.antialias, &mut self.lines);
+    }
+
+    // draw the state onto img with the blend of the context
+    pub fn draw<T: PurrShape>(&self, img: &mut RgbaImage, state: &PurrState<T>) {
+        self.draw_shape(img, &state.shape, &state.color);
+    }
+
+    // draw the shape onto img, which may be larger than the context, like draw
+    pub fn draw_shape<T: PurrShape>(&self, img: &mut RgbaImage, shape: &T, color: &Rgba<u8>) {
+        if !self.antialias {
+            return shape.draw_with(img, color, &self.blend);
+        }
+        let (w, h) = img.dimensions();
+        let mut lines = Vec::new();
+        shape.rasterize_coverage_into(w, h, &mut lines);
+        for line in lines {
+            line.draw_with(img, color, &self.blend);
+        }
+    }
+
+    // keep the transparent areas of the input transparent in the output. the alpha of
+    // the input is folded into the weights and the input is made opaque, so the shapes
+    // only reproduce the opaque areas, then the output is cut out by the alpha
+    pub fn with_transparency(mut self) -> Self {
+        if self.alpha_mask.is_some() || self.origin_img.pixels().all(|p| p.0[3] == 255) {
+            return self;
+        }
+        let mut origin_img = (*self.origin_img).clone();
+        let mask = GrayImage::from_fn(self.w, self.h, |x, y| {
+            Luma([origin_img.get_pixel(x, y).0[3]])
+        });
+        for p in origin_img.pixels_mut() {
+            p.0[3] = 255;
+        }
+        self.metric = PurrMetric::new(self.metric.space, &origin_img);
+        self.origin_img = Arc::new(origin_img);
+        self.alpha_mask = Some(Arc::new(mask));
+        let weights = self.weights.take().map(|w| (*w).clone());
+        self.set_weights(weights)
+    }
+
+    // anchor the random shapes at pixels picked with the probability proportional to
+    // their residual, instead of uniformly
+    pub fn with_residual_sampling(mut self, enabled: bool) -> Self {
+        self.sampler = None;
+        if enabled {
+            let cur = self.current_img.read().unwrap();
+            let sampler = ResidualSampler::new(
+                &self.origin_img,
+                &cur,
+                self.weights.as_deref(),
+                &self.metric,
+            );
+            self.sampler = Some(Arc::new(RwLock::new(sampler)));
+        }
+        self
+    }
+
+    // draw the state onto current_img, and take its score
+    pub fn add_state<T: PurrShape>(&mut self, state: &PurrState<T>) {
+        let mut cur = self.current_img.write().unwrap();
+        self.draw(&mut cur, state);
+        self.score = state.score;
+        self.sse = state.sse;
+        if let Some(sampler) = &self.sampler {
+            let lines = self.rasterize(&state.shape);
+            sampler.write().unwrap().update(
+                &self.origin_img,
+                &cur,
+                &lines,
+                self.weights.as_deref(),
+                &self.metric,
+            );
+        }
+    }
+
+    // random shape, anchored by the sampler if there is one
+    pub fn random_shape<T: PurrShape>(&mut self) -> T {
+        match &self.sampler {
+            Some(sampler) => {
+                let (x, y) = sampler.read().unwrap().sample(&mut self.rng);
+                T::random_at(x as i32, y as i32, self.w, self.h, &mut self.rng)
+            }
+            None => T::random(self.w, self.h, &mut self.rng),
+        }
+    }
+
+    // score and sse of img, which is in the size of the context
+    pub fn full_score(&self, img: &RgbaImage) -> (f64, u64) {
+        let weights = self.weights.as_deref();
+        let sse = diff_full(&self.origin_img, img, weights, &self.metric);
+        let score = match self.objective {
+            PurrObjective::Rms => score_of(&self.origin_img, weights, sse),
+            PurrObjective::Ssim => dssim_full(&self.origin_img, img),
+        };
+        (score, sse)
+    }
+
+    fn rescore(&mut self) {
+        let cur = self.current_img.read().unwrap();
+        let (score, sse) = self.full_score(&cur);
+        if let Some(sampler) = &self.sampler {
+            // in place, the sampler is shared with the workers
+            *sampler.write().unwrap() = ResidualSampler::new(
+                &self.origin_img,
+                &cur,
+                self.weights.as_deref(),
+                &self.metric,
+            );
+        }
+        self.score = score;
+        self.sse = sse;
+    }
+
+    // the context at factor of the resolution, with the canvas cleared to the background.
+    // the origin image, weights and metric are resized, the other options are kept
+    pub fn resized(&self, factor: f64) -> Self {
+        let w = std::cmp::max((self.w as f64 * factor).round() as u32, 1);
+        let h = std::cmp::max((self.h as f64 * factor).round() as u32, 1);
+        let mut ctx = self.clone();
+        if w != self.w || h != self.h {
+            let origin_img = image::imageops::resize(&*self.origin_img, w, h, FilterType::Triangle);
+            ctx.metric = PurrMetric::new(self.metric.space, &origin_img);
+            ctx.origin_img = Arc::new(origin_img);
+            ctx.weights = self.weights.as_ref().map(|m| Arc::new(m.resize(w, h)));
+            ctx.alpha_mask = self
+                .alpha_mask
+                .as_ref()
+                .map(|m| Arc::new(image::imageops::resize(&**m, w, h, FilterType::Triangle)));
+            ctx.w = w;
+            ctx.h = h;
+            ctx.scale = self.scale * self.w as f32 / w as f32;
+        }
+        ctx.current_img = Arc::new(RwLock::new(RgbaImage::from_pixel(w, h, self.bg)));
+        ctx = ctx.with_residual_sampling(self.sampler.is_some());
+        ctx.rescore();
+        ctx
+    }
+
+    // the context of the region at (x, y) in the size of w x h, with its current image.
+    // the origin image and weights are cropped, the other options are kept
+    pub fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> Self {
+        let mut ctx = self.clone();
+        let origin_img = image::imageops::crop_imm(&*self.origin_img, x, y, w, h).to_image();
+        let current_img = {
+            let cur = self.current_img.read().unwrap();
+            image::imageops::crop_imm(&*cur, x, y, w, h).to_image()
+        };
+        ctx.metric = PurrMetric::new(self.metric.space, &origin_img);
+        ctx.origin_img = Arc::new(origin_img);
+        ctx.current_img = Arc::new(RwLock::new(current_img));
+        ctx.weights = self.weights.as_ref().map(|m| Arc::new(m.crop(x, y, w, h)));
+        ctx.alpha_mask = self
+            .alpha_mask
+            .as_ref()
+            .map(|m| Arc::new(image::imageops::crop_imm(&**m, x, y, w, h).to_image()));
+        ctx.w = w;
+        ctx.h = h;
+        ctx = ctx.with_residual_sampling(self.sampler.is_some());
+        ctx.rescore();
+        ctx
+    }
+
+    // replace the current image by the rendering of the states
+    pub fn set_states<T: PurrShape>(&mut self, states: &[PurrState<T>]) {
+        let img = self.render(states);
+        *self.current_img.write().unwrap() = img;
+        self.rescore();
+    }
+
+    // the background with the states drawn in order
+    pub fn render<T: PurrShape>(&self, states: &[PurrState<T>]) -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(self.w, self.h, self.bg);
+        for state in states {
+            self.draw(&mut img, state);
+        }
+        img
+    }
+
+    // like render, in s times the size with the shapes scaled, for the output
+    pub fn render_scaled<T: PurrShape>(&self, states: &[PurrState<T>], s: f32) -> RgbaImage {
+        let w = std::cmp::max((self.w as f32 * s) as u32, 1);
+        let h = std::cmp::max((self.h as f32 * s) as u32, 1);
+        let mut img = RgbaImage::from_pixel(w, h, self.bg);
+        for state in states {
+            self.draw_shape(&mut img, &state.shape.scale(s as f64), &state.color);
+        }
+        img
+    }
+
+    // score and sse after drawing the lines in color onto cur, which is the current image
+    pub fn partial_score(
+        &self,
+        cur: &RgbaImage,
+        lines: &Vec<Scanline>,
+        color: Rgba<u8>,
+    ) -> (f64, u64) {
+        let weights = self.weights.as_deref();
+        let sse = diff_partial_with_color(
+            &self.origin_img,
+            cur,
+            lines,
+            self.sse,
+            color,
+            weights,
+            &self.metric,
+            &self.blend,
+        );
+        let score = match self.objective {
+            PurrObjective::Rms => score_of(&self.origin_img, weights, sse),
+            PurrObjective::Ssim => dssim_partial_with_color(
+                &self.origin_img,
+                cur,
+                lines,
+                self.score,
+                color,
+                &self.blend,
+            ),
+        };
+        (score, sse)
+    }
+
+    // mean ssim of the current image, regardless of the objective
+    pub fn ssim(&self) -> f64 {
+        ssim(&self.origin_img, &self.current_img.read().unwrap())
+    }
+
+    // fix the seed, so that runs with the same input, parameters, seed and
+    // thread number always produce the same result
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self.rng = self.stream_rng(0);
+        self
+    }
+
+    // rng for the given stream, stream 0 is the context itself and
+    // stream i + 1 belongs to the i-th worker
+    pub fn stream_rng(&self, stream: u64) -> SmallRng {
+        match self.seed {
+            Some(seed) => {
+                SmallRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            }
+            None => SmallRng::from_entropy(),
         }
     }
 }
@@ -157,6 +658,41 @@ impl PurrContext {
 pub trait PurrModel<T: PurrShape> {
     fn step(&mut self) -> PurrState<T>;
     fn add_state(&mut self, state: &PurrState<T>);
+    fn context(&self) -> &PurrContext;
+    fn context_mut(&mut self) -> &mut PurrContext;
+    // called on the copy of each worker, to share the work of one step
+    fn split(&mut self, _workers: u32) {}
+}
+
+// the number of climbs for each worker
+fn split_m(m: u32, workers: u32) -> u32 {
+    m.div_ceil(workers)
+}
+
+// n: random candidates of each climb, m: number of climbs
+pub fn check_search_params(n: u32, m: u32, thread_number: u32) -> PurrResult<()> {
+    if n == 0 || m == 0 || thread_number == 0 {
+        return Err(PurrError::InvalidParam(format!(
+            "invalid search params n={}, m={}, threads={}, all of them should be positive",
+            n, m, thread_number
+        )));
+    }
+    if m < thread_number {
+        return Err(PurrError::InvalidParam(format!(
+            "number of climbs {} is less than the number of threads {}, each thread climbs at least once",
+            m, thread_number
+        )));
+    }
+    let worker_m = split_m(m, thread_number);
+    if worker_m * thread_number != m {
+        warn!(
+            "number of climbs {} is not a multiple of threads {}, {} climbs will be done",
+            m,
+            thread_number,
+            worker_m * thread_number
+        );
+    }
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
@@ -185,19 +721,128 @@ impl<T: PurrShape> PurrModel<T> for PurrHillClimbModel {
     }
 
     fn add_state(&mut self, state: &PurrState<T>) {
-        let mut cur = self.context.current_img.write().unwrap();
-        state.shape.draw(&mut cur, &state.color);
-        self.context.score = state.score;
+        self.context.add_state(state);
+    }
+
+    fn context(&self) -> &PurrContext {
+        &self.context
+    }
+
+    fn context_mut(&mut self) -> &mut PurrContext {
+        &mut self.context
+    }
+
+    fn split(&mut self, workers: u32) {
+        self.m = split_m(self.m, workers);
     }
 }
 
-pub struct PurrMultiThreadRunner<T: PurrShape> {
+#[derive(Clone, Debug)]
+pub struct PurrAnnealingModel {
+    pub context: PurrContext,
+    pub n: u32,
+    pub m: u32,
+    pub schedule: AnnealingSchedule,
+}
+
+impl PurrAnnealingModel {
+    pub fn new(context: PurrContext, n: u32, m: u32, schedule: AnnealingSchedule) -> Self {
+        PurrAnnealingModel {
+            context,
+            n,
+            m,
+            schedule,
+        }
+    }
+}
+
+impl<T: PurrShape> PurrModel<T> for PurrAnnealingModel {
+    fn step(&mut self) -> PurrState<T> {
+        best_anneal(&mut self.context, self.n, self.m, &self.schedule)
+    }
+
+    fn add_state(&mut self, state: &PurrState<T>) {
+        self.context.add_state(state);
+    }
+
+    fn context(&self) -> &PurrContext {
+        &self.context
+    }
+
+    fn context_mut(&mut self) -> &mut PurrContext {
+        &mut self.context
+    }
+
+    fn split(&mut self, workers: u32) {
+        self.m = split_m(self.m, workers);
+    }
+}
+
+// all the built-in models, so that the search strategy can be picked at runtime
+#[derive(Clone, Debug)]
+pub enum PurrSearchModel {
+    HillClimb(PurrHillClimbModel),
+    Annealing(PurrAnnealingModel),
+}
+
+impl PurrSearchModel {
+    pub fn context(&self) -> &PurrContext {
+        match self {
+            PurrSearchModel::HillClimb(m) => &m.context,
+            PurrSearchModel::Annealing(m) => &m.context,
+        }
+    }
+
+    pub fn context_mut(&mut self) -> &mut PurrContext {
+        match self {
+            PurrSearchModel::HillClimb(m) => &mut m.context,
+            PurrSearchModel::Annealing(m) => &mut m.context,
+        }
+    }
+}
+
+impl<T: PurrShape> PurrModel<T> for PurrSearchModel {
+    fn step(&mut self) -> PurrState<T> {
+        match self {
+            PurrSearchModel::HillClimb(m) => m.step(),
+            PurrSearchModel::Annealing(m) => m.step(),
+        }
+    }
+
+    fn add_state(&mut self, state: &PurrState<T>) {
+        match self {
+            PurrSearchModel::HillClimb(m) => m.add_state(state),
+            PurrSearchModel::Annealing(m) => m.add_state(state),
+        }
+    }
+
+    fn context(&self) -> &PurrContext {
+        PurrSearchModel::context(self)
+    }
+
+    fn context_mut(&mut self) -> &mut PurrContext {
+        PurrSearchModel::context_mut(self)
+    }
+
+    fn split(&mut self, workers: u32) {
+        match self {
+            PurrSearchModel::HillClimb(m) => PurrModel::<T>::split(m, workers),
+            PurrSearchModel::Annealing(m) => PurrModel::<T>::split(m, workers),
+        }
+    }
+}
+
+// called by the runners with the index and the state of each added shape
+pub type OnStep<T> = Box<dyn FnMut(usize, PurrState<T>, &PurrContext) + Sync + Send>;
+
+pub struct PurrMultiThreadRunner<T: PurrShape, M = PurrSearchModel> {
     pub shape_number: u32,
     pub thread_number: u32,
     pub states: Vec<PurrState<T>>,
     pub rxs: Vec<Receiver<PurrState<T>>>,
     pub txs: Vec<Sender<PurrWorkerCmd>>,
-    pub on_step: Option<Box<dyn FnMut(usize, PurrState<T>) + Sync + Send>>,
+    pub on_step: Option<OnStep<T>>,
+    model: PhantomData<fn() -> M>,
 }
 
 pub trait PurrModelRunner {
@@ -206,12 +851,20 @@ pub trait PurrModelRunner {
     fn step(&mut self, model: &mut Self::M);
     fn stop(&mut self);
     fn run(&mut self, model: &mut Self::M, score: f64);
+    // like run, but the first shapes are placed at lower resolutions, each of the
+    // levels halves the resolution
+    fn run_pyramid(&mut self, model: &mut Self::M, score: f64, levels: u32);
+    // climb the added shapes again, returns the number of changed shapes
+    fn refine(&mut self, model: &mut Self::M, age: u32) -> usize;
+    // remove the shapes which contribute less than threshold to the score, the
+    // threshold bounds the loss of all the removals, returns the number of removed shapes
+    fn prune(&mut self, model: &mut Self::M, threshold: f64) -> usize;
     fn get_svg(&self, context: &PurrContext, idx: usize) -> String;
-    fn save(&self, context: &PurrContext, output: &str);
+    fn save(&self, context: &PurrContext, output: &str) -> PurrResult<()>;
     fn get_last_shape(&self) -> String;
 }
 
-impl<T: PurrShape> Default for PurrMultiThreadRunner<T> {
+impl<T: PurrShape, M> Default for PurrMultiThreadRunner<T, M> {
     fn default() -> Self {
         PurrMultiThreadRunner {
             shape_number: 100,
@@ -220,38 +873,22 @@ impl<T: PurrShape> Default for PurrMultiThreadRunner<T> {
             rxs: Vec::new(),
             txs: Vec::new(),
             on_step: None,
+            model: PhantomData,
         }
     }
 }
 
-impl<T: 'static + PurrShape> PurrModelRunner for PurrMultiThreadRunner<T> {
-    type M = PurrHillClimbModel;
+impl<T, M> PurrModelRunner for PurrMultiThreadRunner<T, M>
+where
+    T: 'static + PurrShape,
+    M: 'static + PurrModel<T> + Clone + Send,
+{
+    type M = M;
     fn init(&mut self, model: &mut Self::M) {
         // stop all threads first
         self.stop();
         self.states.clear();
-        // new pool
-        if self.txs.is_empty() && self.rxs.is_empty() {
-            let pool = ThreadPool::new(self.thread_number as usize);
-            // spawn workers
-            let mut worker_model_m = model.m / self.thread_number;
-            if model.m % self.thread_number != 0 {
-                worker_model_m += 1;
-            }
-            for _ in 0..self.thread_number {
-                let (cmd_s, cmd_r) = bounded(1);
-                let (res_s, res_r) = bounded(1);
-                let mut worker_model = model.clone();
-                worker_model.m = worker_model_m;
-                worker_model.context.rng = SmallRng::from_entropy();
-                let mut worker = PurrWorker::new(worker_model, cmd_r, res_s);
-                self.txs.push(cmd_s);
-                self.rxs.push(res_r);
-                pool.execute(move || {
-                    worker.start();
-                });
-            }
-        }
+        self.spawn(model);
     }
 
     fn step(&mut self, model: &mut Self::M) {
@@ -278,12 +915,13 @@ impl<T: 'static + PurrShape> PurrModelRunner for PurrMultiThreadRunner<T> {
 
         match &mut self.on_step {
             None => {}
-            Some(f) => f(self.states.len(), best_state),
+            Some(f) => f(self.states.len(), best_state, model.context()),
         }
 
         // update worker threads
         for tx in &self.txs {
-            tx.send(PurrWorkerCmd::UpdateScore(model.context.score))
+            let ctx = model.context();
+            tx.send(PurrWorkerCmd::UpdateScore(ctx.score, ctx.sse))
                 .unwrap();
         }
     }
@@ -304,7 +942,7 @@ impl<T: 'static + PurrShape> PurrModelRunner for PurrMultiThreadRunner<T> {
         if score > 0.0 && score < 1.0 {
             loop {
                 self.step(model);
-                if model.context.score <= score {
+                if model.context().score <= score {
                     break;
                 }
             }
@@ -317,111 +955,132 @@ impl<T: 'static + PurrShape> PurrModelRunner for PurrMultiThreadRunner<T> {
         self.stop();
     }
 
-    fn get_svg(&self, context: &PurrContext, idx: usize) -> String {
-        let mut output = "".to_owned();
-        output += &format!(
-            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">",
-            context.w, context.h
-        );
-        output += &format!(
-            "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#{:02X}{:02X}{:02X}\"/>",
-            context.w, context.h, context.bg.0[0], context.bg.0[1], context.bg.0[2]
-        );
-        output += "<g transform=\"scale(1) translate(0.5 0.5)\">";
+    fn run_pyramid(&mut self, model: &mut Self::M, score: f64, levels: u32) {
+        let levels = pyramid_levels(model.context(), levels);
+        if levels <= 1 {
+            self.run(model, score);
+            return;
+        }
+        self.stop();
+        self.states.clear();
 
-        for i in 0..=idx {
-            if i >= self.states.len() {
-                break;
+        // half of the shapes are placed at the lower resolutions
+        let level_shapes = self.shape_number / 2 / (levels - 1);
+        let full = model.context().clone();
+        // the states as they were placed, with the size of their level, so that each
+        // one is scaled only once to the size of a later level
+        let mut placed: Vec<(PurrState<T>, u32, u32)> = Vec::new();
+        for level in (1..levels).rev() {
+            let mut ctx = full.resized(0.5f64.powi(level as i32));
+            self.states = scale_states(&placed, ctx.w, ctx.h);
+            ctx.set_states(&self.states);
+            info!(
+                "level {}: {} shapes at {}x{}",
+                level, level_shapes, ctx.w, ctx.h
+            );
+            let mut level_model = model.clone();
+            *level_model.context_mut() = ctx;
+            self.spawn(&mut level_model);
+            for _ in 0..level_shapes {
+                self.step(&mut level_model);
             }
-            output += &self.states[i].to_svg();
+            self.stop();
+            let (w, h) = (level_model.context().w, level_model.context().h);
+            let new = &self.states[placed.len()..];
+            placed.extend(new.iter().map(|s| (*s, w, h)));
         }
 
-        output += "</g>";
-        output += "</svg>";
-        output
+        // then the full resolution
+        self.states = scale_states(&placed, full.w, full.h);
+        model.context_mut().set_states(&self.states);
+        self.spawn(model);
+        if score > 0.0 && score < 1.0 {
+            while model.context().score > score {
+                self.step(model);
+            }
+        } else {
+            while (self.states.len() as u32) < self.shape_number {
+                self.step(model);
+            }
+        }
+        self.stop();
+    }
+
+    fn refine(&mut self, model: &mut Self::M, age: u32) -> usize {
+        let changed = refine_states(model.context_mut(), &mut self.states, age);
+        // update worker threads
+        for tx in &self.txs {
+            let ctx = model.context();
+            tx.send(PurrWorkerCmd::UpdateScore(ctx.score, ctx.sse))
+                .unwrap();
+        }
+        changed
+    }
+
+    fn prune(&mut self, model: &mut Self::M, threshold: f64) -> usize {
+        let removed = prune_states(model.context_mut(), &mut self.states, threshold);
+        // update worker threads
+        for tx in &self.txs {
+            let ctx = model.context();
+            tx.send(PurrWorkerCmd::UpdateScore(ctx.score, ctx.sse))
+                .unwrap();
+        }
+        removed
+    }
+
+    fn get_svg(&self, context: &PurrContext, idx: usize) -> String {
+        let end = std::cmp::min(idx.saturating_add(1), self.states.len());
+        states_svg(context, &self.states[..end])
     }
 
     fn get_last_shape(&self) -> String {
         match self.states.last() {
-            Some(s) => s.to_svg(),
+            Some(s) => s.to_svg(None),
             None => "".to_string(),
         }
     }
 
-    fn save(&self, context: &PurrContext, output: &str) {
-        // save result
-        let suffix = Path::new(output)
-            .extension()
-            .and_then(OsStr::to_str)
-            .unwrap_or("png");
-        let should_format = output.find("{").is_some();
-        let save_frames = should_format && suffix != "gif";
-        for i in 0..self.states.len() {
-            let last = i == self.states.len() - 1;
-            if last || save_frames {
-                let outfile = if should_format {
-                    //output.to_string()
-                    output.format(&[i + 1])
-                } else {
-                    output.to_string()
-                };
-                match suffix {
-                    "svg" => {
-                        let mut out = OpenOptions::new()
-                            .write(true)
-                            .create(true)
-                            .open(outfile)
-                            .unwrap();
-                        out.write_all(self.get_svg(context, i).as_bytes()).unwrap();
-                    }
-                    "gif" => {
-                        let out = OpenOptions::new()
-                            .write(true)
-                            .create(true)
-                            .open(output)
-                            .unwrap();
-
-                        let mut encoder =
-                            Encoder::new(out, context.w as u16, context.h as u16, &[0; 0]).unwrap();
-                        encoder.set(Repeat::Infinite).unwrap();
-
-                        for n in 0..self.shape_number {
-                            let frame = self.get_svg(context, n as usize);
-                            info!("exporting {} frame", n + 1);
-                            let svg = nsvg::parse_str(&frame, nsvg::Units::Pixel, 96.0).unwrap();
-                            let (width, height, mut raw) =
-                                svg.rasterize_to_raw_rgba(context.scale).unwrap();
-                            // let img = image::RgbaImage::from_raw(width, height, raw).unwrap();
-                            let frame = Frame::from_rgba(width as u16, height as u16, &mut raw);
-                            encoder.write_frame(&frame).unwrap();
-                        }
-
-                        // save final result then
-                        let svg_str = self.get_svg(context, i);
-                        let img = rasterize_svg(&svg_str, context.scale);
-                        let final_res = format!("{}.png", output);
-                        img.save(&final_res).unwrap();
-                        debug!("gif result saved to {}", final_res);
-                    }
-                    _ => {
-                        // generate svg, then rasterize it
-                        // for anti-aliasing
-                        let svg_str = self.get_svg(context, i);
-                        let img = rasterize_svg(&svg_str, context.scale);
-                        img.save(outfile).unwrap();
-                    }
-                }
+    fn save(&self, context: &PurrContext, output: &str) -> PurrResult<()> {
+        save_steps(
+            context,
+            output,
+            self.states.len(),
+            self.shape_number as usize,
+            |i| self.get_svg(context, i),
+            |i| rasterize_states(context, &self.states[..=i]),
+        )
+    }
+}
+
+impl<T, M> PurrMultiThreadRunner<T, M>
+where
+    T: 'static + PurrShape,
+    M: 'static + PurrModel<T> + Clone + Send,
+{
+    // spawn the workers, the added states are kept
+    fn spawn(&mut self, model: &mut M) {
+        if self.txs.is_empty() && self.rxs.is_empty() {
+            let pool = ThreadPool::new(self.thread_number as usize);
+            // spawn workers
+            for i in 0..self.thread_number {
+                let (cmd_s, cmd_r) = bounded(1);
+                let (res_s, res_r) = bounded(1);
+                let mut worker_model = model.clone();
+                worker_model.split(self.thread_number);
+                worker_model.context_mut().rng = model.context().stream_rng(i as u64 + 1);
+                let mut worker = PurrWorker::new(worker_model, cmd_r, res_s);
+                self.txs.push(cmd_s);
+                self.rxs.push(res_r);
+                pool.execute(move || {
+                    worker.start();
+                });
             }
         }
     }
 }
 
-impl<T: 'static + PurrShape> PurrMultiThreadRunner<T> {
-    pub fn new(
-        shape_number: u32,
-        thread_number: u32,
-        on_step: Option<Box<dyn FnMut(usize, PurrState<T>) + Sync + Send>>,
-    ) -> Self {
+impl<T: 'static + PurrShape, M> PurrMultiThreadRunner<T, M> {
+    pub fn new(shape_number: u32, thread_number: u32, on_step: Option<OnStep<T>>) -> Self {
         PurrMultiThreadRunner {
             shape_number,
             thread_number,
@@ -429,14 +1088,250 @@ impl<T: 'static + PurrShape> PurrMultiThreadRunner<T> {
             rxs: Vec::new(),
             txs: Vec::new(),
             on_step,
+            model: PhantomData,
+        }
+    }
+}
+
+// save the output of the n steps of a runner, svg and raster give the output after the
+// step of the given index. with {} in output, the output of each step is saved. a gif
+// gets a frame for each of the first frames steps, and the final png next to it
+fn save_steps<S, R>(
+    context: &PurrContext,
+    output: &str,
+    n: usize,
+    frames: usize,
+    svg: S,
+    raster: R,
+) -> PurrResult<()>
+where
+    S: Fn(usize) -> String,
+    R: Fn(usize) -> PurrResult<RgbaImage>,
+{
+    let suffix = Path::new(output)
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("png");
+    let should_format = output.find("{").is_some();
+    let save_frames = should_format && suffix != "gif";
+    if suffix == "svg" && context.blend.tables().is_some() {
+        warn!("svg viewers composite in srgb, the output will not look like the linear blend");
+    }
+    for i in 0..n {
+        let last = i == n - 1;
+        if last || save_frames {
+            let outfile = if should_format {
+                output.format(&[i + 1])
+            } else {
+                output.to_string()
+            };
+            match suffix {
+                "svg" => {
+                    let mut out = File::create(outfile)?;
+                    out.write_all(svg(i).as_bytes())?;
+                }
+                "gif" => {
+                    let out = File::create(output)?;
+
+                    let mut encoder =
+                        Encoder::new(out, context.w as u16, context.h as u16, &[0; 0])?;
+                    encoder.set(Repeat::Infinite)?;
+
+                    for f in 0..frames {
+                        info!("exporting {} frame", f + 1);
+                        let img = raster(std::cmp::min(f, n - 1))?;
+                        let (width, height) = img.dimensions();
+                        let mut raw = img.into_raw();
+                        let frame = Frame::from_rgba(width as u16, height as u16, &mut raw);
+                        encoder.write_frame(&frame)?;
+                    }
+
+                    // save final result then
+                    let img = raster(i)?;
+                    let final_res = format!("{}.png", output);
+                    img.save(&final_res)?;
+                    debug!("gif result saved to {}", final_res);
+                }
+                _ => {
+                    let img = raster(i)?;
+                    img.save(outfile)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// min short side of the lowest level of run_pyramid
+const PYRAMID_MIN_SIZE: u32 = 16;
+
+// the number of levels, less than levels if the short side of the lowest level would
+// be smaller than PYRAMID_MIN_SIZE. nothing can be rasterized on a canvas of one row
+fn pyramid_levels(context: &PurrContext, levels: u32) -> u32 {
+    let mut side = std::cmp::min(context.w, context.h);
+    let mut max = 1;
+    while max < levels && side / 2 >= PYRAMID_MIN_SIZE {
+        side /= 2;
+        max += 1;
+    }
+    if max < levels {
+        warn!(
+            "{} levels are too many for {}x{}, {} levels are used",
+            levels, context.w, context.h, max
+        );
+    }
+    max
+}
+
+// the states placed in images of the given sizes, scaled to w x h
+fn scale_states<T: PurrShape>(
+    placed: &[(PurrState<T>, u32, u32)],
+    w: u32,
+    h: u32,
+) -> Vec<PurrState<T>> {
+    placed
+        .iter()
+        .map(|(state, pw, ph)| PurrState {
+            shape: state
+                .shape
+                .transform(w as f64 / *pw as f64, h as f64 / *ph as f64),
+            ..*state
+        })
+        .collect()
+}
+
+// opening svg tag of the context, with the palette style and the background.
+// with an alpha mask, the content is in a masked group, which svg_end closes
+fn svg_begin(context: &PurrContext) -> String {
+    // the compositing of the linear blend, for the renderers which support it, most
+    // viewers ignore it and composite in srgb. it is inherited, the mask keeps srgb so
+    // that the gray values are the alpha
+    let (svg_attr, mask_attr) = match context.blend.tables() {
+        Some(_) => (
+            " color-interpolation=\"linearRGB\"",
+            " color-interpolation=\"sRGB\"",
+        ),
+        None => ("", ""),
+    };
+    let mut output = "".to_owned();
+    output += &format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\"{}>",
+        context.w, context.h, svg_attr
+    );
+    if let Some(mask) = context.alpha_mask.as_deref() {
+        // luminance mask, the gray values are the alpha
+        let mut png = Vec::new();
+        // encoding into memory does not fail
+        DynamicImage::ImageLuma8(mask.clone())
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        output += &format!(
+            "<defs><mask id=\"alpha\"{}><image xmlns:xlink=\"http://www.w3.org/1999/xlink\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" xlink:href=\"data:image/png;base64,{}\"/></mask></defs>",
+            mask_attr,
+            context.w,
+            context.h,
+            base64(&png)
+        );
+        output += "<g mask=\"url(#alpha)\">";
+    }
+    let mut bg_class = "".to_owned();
+    if let Some(p) = context.palette.as_deref() {
+        // fill attributes are kept, the classes are for reusing the palette
+        output += &format!("<style>{}</style>", palette_css(p));
+        if let Some(i) = palette_index(p, &context.bg) {
+            bg_class = format!("class=\"c{}\" ", i);
         }
     }
+    output += &format!(
+        "<rect {}x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#{:02X}{:02X}{:02X}\"/>",
+        bg_class, context.w, context.h, context.bg.0[0], context.bg.0[1], context.bg.0[2]
+    );
+    output
+}
+
+fn svg_end(context: &PurrContext) -> &'static str {
+    match context.alpha_mask {
+        Some(_) => "</g></svg>",
+        None => "</svg>",
+    }
 }
 
-pub fn rasterize_svg(svg_str: &str, scale: f32) -> RgbaImage {
-    let svg = nsvg::parse_str(&svg_str, nsvg::Units::Pixel, 96.0).unwrap();
-    let (width, height, raw) = svg.rasterize_to_raw_rgba(scale).unwrap();
-    image::RgbaImage::from_raw(width, height, raw).unwrap()
+// svg of the states drawn onto the background of the context
+fn states_svg<T: PurrShape>(context: &PurrContext, states: &[PurrState<T>]) -> String {
+    let palette = context.palette.as_deref().map(|p| p.as_slice());
+    let mut output = svg_begin(context);
+    output += "<g transform=\"scale(1) translate(0.5 0.5)\">";
+    for state in states {
+        output += &state.to_svg(palette);
+    }
+    output += "</g>";
+    output += svg_end(context);
+    output
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut n = 0u32;
+        for (i, b) in chunk.iter().enumerate() {
+            n |= (*b as u32) << (16 - 8 * i);
+        }
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+fn rasterize_shape<T: PurrShape>(
+    shape: &T,
+    w: u32,
+    h: u32,
+    antialias: bool,
+    lines: &mut Vec<Scanline>,
+) {
+    if antialias {
+        shape.rasterize_coverage_into(w, h, lines);
+    } else {
+        shape.rasterize_into(w, h, lines);
+    }
+}
+
+pub fn rasterize_svg(svg_str: &str, scale: f32) -> PurrResult<RgbaImage> {
+    let svg = nsvg::parse_str(&svg_str, nsvg::Units::Pixel, 96.0)?;
+    let (width, height, raw) = svg.rasterize_to_raw_rgba(scale)?;
+    // the buffer of nsvg is always width x height rgba
+    Ok(image::RgbaImage::from_raw(width, height, raw).unwrap())
+}
+
+// the output raster of the states, cut out by the alpha mask which nsvg ignores.
+// the svg is rasterized by nsvg for anti-aliasing, but it composites in srgb like the
+// svg viewers, so with the linear blend the shapes are drawn like in the search
+fn rasterize_states<T: PurrShape>(
+    context: &PurrContext,
+    states: &[PurrState<T>],
+) -> PurrResult<RgbaImage> {
+    let mut img = match context.blend.tables() {
+        Some(_) => context.render_scaled(states, context.scale),
+        None => rasterize_svg(&states_svg(context, states), context.scale)?,
+    };
+    cut_out(context, &mut img);
+    Ok(img)
+}
+
+// scale the alpha of img, which is the output of the context, by the alpha mask
+fn cut_out(context: &PurrContext, img: &mut RgbaImage) {
+    if let Some(mask) = context.alpha_mask.as_deref() {
+        let mask = image::imageops::resize(mask, img.width(), img.height(), FilterType::Triangle);
+        for (p, a) in img.pixels_mut().zip(mask.pixels()) {
+            p.0[3] = div255(p.0[3] as u32 * a.0[0] as u32) as u8;
+        }
+    }
 }
 
 #[macro_export]
@@ -454,21 +1349,200 @@ macro_rules! mt_runner {
 #[macro_export]
 macro_rules! model_runner {
     ($mode: expr, $sn: expr, $tn: expr, $cb_creator: expr) => {{
-        let runner: Box<dyn PurrModelRunner<M = PurrHillClimbModel> + Sync + Send> = match $mode {
-            0 => mt_runner!(Combo, $sn, $tn, $cb_creator),
-            1 => mt_runner!(Triangle, $sn, $tn, $cb_creator),
-            2 => mt_runner!(Rectangle, $sn, $tn, $cb_creator),
-            3 => mt_runner!(Ellipse, $sn, $tn, $cb_creator),
-            4 => mt_runner!(Circle, $sn, $tn, $cb_creator),
-            5 => mt_runner!(RotatedRectangle, $sn, $tn, $cb_creator),
-            6 => mt_runner!(Quadratic, $sn, $tn, $cb_creator),
-            7 => mt_runner!(RotatedEllipse, $sn, $tn, $cb_creator),
-            8 => mt_runner!(Polygon, $sn, $tn, $cb_creator),
-            _ => {
-                error!("unsupported mode {}", $mode);
-                unreachable!()
-            }
+        model_runner!($mode, $sn, $tn, $cb_creator, PurrSearchModel)
+    }};
+    ($mode: expr, $sn: expr, $tn: expr, $cb_creator: expr, $model: ty) => {{
+        let runner: PurrResult<Box<dyn PurrModelRunner<M = $model> + Sync + Send>> = match $mode {
+            0 => Ok(mt_runner!(Combo, $sn, $tn, $cb_creator)),
+            1 => Ok(mt_runner!(Triangle, $sn, $tn, $cb_creator)),
+            2 => Ok(mt_runner!(Rectangle, $sn, $tn, $cb_creator)),
+            3 => Ok(mt_runner!(Ellipse, $sn, $tn, $cb_creator)),
+            4 => Ok(mt_runner!(Circle, $sn, $tn, $cb_creator)),
+            5 => Ok(mt_runner!(RotatedRectangle, $sn, $tn, $cb_creator)),
+            6 => Ok(mt_runner!(Quadratic, $sn, $tn, $cb_creator)),
+            7 => Ok(mt_runner!(RotatedEllipse, $sn, $tn, $cb_creator)),
+            8 => Ok(mt_runner!(Polygon, $sn, $tn, $cb_creator)),
+            m => Err(PurrError::UnsupportedMode(m as i32)),
         };
         runner
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageOutputFormat;
+
+    #[test]
+    fn test_from_bytes() {
+        let mut img = RgbaImage::new(64, 48);
+        for (x, y, p) in img.enumerate_pixels_mut() {
+            p.0 = [(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255];
+        }
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(img.clone())
+            .write_to(&mut bytes, ImageOutputFormat::Png)
+            .unwrap();
+
+        // both are resized to 32 x 24
+        let a = PurrContext::from_bytes(&bytes, 32, 64, 128, None).unwrap();
+        let b = PurrContext::from_rgba(img, 32, 64, 128, None);
+        assert_eq!((a.w, a.h), (32, 24));
+        assert_eq!(a.origin_img, b.origin_img);
+        assert_eq!(a.bg, b.bg);
+        assert_eq!(a.sse, b.sse);
+        assert_eq!(a.scale, 2.0);
+
+        assert!(PurrContext::from_bytes(&bytes[..16], 32, 64, 128, None).is_err());
+    }
+
+    #[test]
+    fn test_seed() {
+        let img = RgbaImage::from_fn(32, 24, |x, y| {
+            Rgba([(x * 8) as u8, (y * 10) as u8, ((x + y) * 4) as u8, 255])
+        });
+        let run = || {
+            let ctx = PurrContext::from_rgba(img.clone(), 32, 32, 128, None).with_seed(Some(7));
+            let mut model = PurrHillClimbModel::new(ctx, 20, 4, 10);
+            let mut runner: PurrMultiThreadRunner<Triangle, PurrHillClimbModel> =
+                PurrMultiThreadRunner::new(5, 2, None);
+            runner.run(&mut model, 0.0);
+            (runner.states, model.context.score, model.context.sse)
+        };
+        let (a, score_a, sse_a) = run();
+        let (b, score_b, sse_b) = run();
+        assert_eq!(a.len(), 5);
+        for (x, y) in a.iter().zip(&b) {
+            assert_eq!(x.shape.to_svg(""), y.shape.to_svg(""));
+            assert_eq!(x.color, y.color);
+            assert_eq!(x.score.to_bits(), y.score.to_bits());
+        }
+        assert_eq!(score_a.to_bits(), score_b.to_bits());
+        assert_eq!(sse_a, sse_b);
+    }
+
+    #[test]
+    fn test_palette_color() {
+        let img = RgbaImage::from_fn(24, 24, |x, y| {
+            Rgba([(x * 10) as u8, (y * 10) as u8, 200 - (x + y) as u8 * 4, 255])
+        });
+        let palette = parse_palette("#000,#fff,#f80,#08f,#3a6,#c3c").unwrap();
+        let lines: Vec<Scanline> = (4..20)
+            .map(|y| Scanline {
+                y,
+                x1: 2,
+                x2: 17,
+                coverage: 255,
+            })
+            .collect();
+        let contexts = vec![
+            PurrContext::from_rgba(img.clone(), 24, 24, 128, None),
+            PurrContext::from_rgba(img.clone(), 24, 24, 128, None).with_metric(PurrColorSpace::Lab),
+            PurrContext::from_rgba(img.clone(), 24, 24, 128, None)
+                .with_objective(PurrObjective::Ssim),
+            PurrContext::from_rgba(img, 24, 24, 128, None).with_blend(Blend::linear()),
+        ];
+        for ctx in contexts {
+            let ctx = ctx.with_palette(palette.clone());
+            assert!(palette_index(&palette, &ctx.bg).is_some());
+            let cur = ctx.current_img.read().unwrap();
+            let color = ctx.compute_color(&cur, &lines, 128);
+            assert!(palette_index(&palette, &color).is_some());
+            assert_eq!(color.0[3], 128);
+            // no other palette color scores better
+            let (score, _) = ctx.partial_score(&cur, &lines, color);
+            for p in &palette {
+                let other = Rgba([p.0[0], p.0[1], p.0[2], 128]);
+                assert!(score <= ctx.partial_score(&cur, &lines, other).0);
+            }
+        }
+
+        // with alpha 1, the unconstrained color is far out of the rgb range
+        let img = RgbaImage::from_pixel(24, 24, Rgba([255, 255, 255, 255]));
+        let palette = parse_palette("#000000,#ffffff").unwrap();
+        let ctx = PurrContext::from_rgba(img, 24, 24, 1, Some(Rgba([0, 0, 0, 255])))
+            .with_palette(palette);
+        let cur = ctx.current_img.read().unwrap();
+        assert_eq!(ctx.compute_color(&cur, &lines, 1), Rgba([255, 255, 255, 1]));
+    }
+
+    #[test]
+    fn test_with_weights() {
+        let ctx = PurrContext::from_rgba(RgbaImage::new(16, 8), 16, 16, 128, None);
+        let map = WeightMap::new(&GrayImage::from_pixel(8, 8, Luma([255])));
+        assert!(matches!(
+            ctx.clone().with_weights(Some(map)),
+            Err(PurrError::InvalidParam(_))
+        ));
+        let map = WeightMap::new(&GrayImage::from_pixel(16, 8, Luma([255])));
+        let ctx = ctx.with_weights(Some(map)).unwrap();
+        assert_eq!(ctx.weights.as_deref().unwrap().total, 16 * 8 * 255);
+        assert!(ctx.with_weights(None).unwrap().weights.is_none());
+    }
+
+    #[test]
+    fn test_pyramid_levels() {
+        let ctx = PurrContext::from_rgba(RgbaImage::new(40, 130), 130, 130, 128, None);
+        // 40, 20 then 10 would be too small
+        assert_eq!(pyramid_levels(&ctx, 10), 2);
+        assert_eq!(pyramid_levels(&ctx, 2), 2);
+        assert_eq!(pyramid_levels(&ctx, 1), 1);
+        assert_eq!(pyramid_levels(&ctx, 0), 1);
+        let ctx = PurrContext::from_rgba(RgbaImage::new(8, 8), 8, 8, 128, None);
+        assert_eq!(pyramid_levels(&ctx, 3), 1);
+    }
+
+    #[test]
+    fn test_scale_states() {
+        let t = Triangle {
+            a: Point { x: 3, y: 2 },
+            b: Point { x: 15, y: 7 },
+            c: Point { x: 8, y: 11 },
+        };
+        let state = PurrState {
+            shape: t,
+            ..PurrState::default()
+        };
+        // placed at two lower levels, the ratios to the full size are not integers and
+        // differ in x and y
+        let placed = vec![(state, 16, 12), (state, 33, 25)];
+        let scaled = scale_states(&placed, 130, 97);
+        for ((_, pw, ph), s) in placed.iter().zip(&scaled) {
+            let (sx, sy) = (130.0 / *pw as f64, 97.0 / *ph as f64);
+            for (p, q) in [(t.a, s.shape.a), (t.b, s.shape.b), (t.c, s.shape.c)].iter() {
+                assert!((q.x as f64 - p.x as f64 * sx).abs() <= 0.5);
+                assert!((q.y as f64 - p.y as f64 * sy).abs() <= 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transparency() {
+        assert_eq!(base64(b"cat"), "Y2F0");
+        assert_eq!(base64(b"purr"), "cHVycg==");
+        assert_eq!(base64(b"purrs"), "cHVycnM=");
+
+        // opaque red on the left, the colors of the transparent pixels are arbitrary
+        let img = RgbaImage::from_fn(16, 16, |x, _| match x {
+            0..=7 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 255, 0, 0]),
+        });
+        let ctx = PurrContext::from_rgba(img, 16, 16, 128, None);
+        assert_eq!(ctx.bg, Rgba([255, 0, 0, 255]));
+        assert!(ctx.sse > 0);
+
+        let ctx = ctx.with_transparency();
+        assert!(ctx.origin_img.pixels().all(|p| p.0[3] == 255));
+        let weights = ctx.weights.as_deref().unwrap();
+        assert_eq!((weights.get(0, 0), weights.get(15, 15)), (255, 0));
+        assert_eq!(ctx.sse, 0);
+        let svg = states_svg::<Triangle>(&ctx, &[]);
+        assert!(svg.contains("mask=\"url(#alpha)\""));
+        assert!(svg.ends_with("</g></svg>"));
+
+        let mut out = RgbaImage::from_pixel(32, 32, Rgba([255, 0, 0, 255]));
+        cut_out(&ctx, &mut out);
+        assert_eq!(out.get_pixel(0, 0).0[3], 255);
+        assert_eq!(out.get_pixel(31, 0).0[3], 0);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::core::{parse_hex_color, PurrResult};
+use crate::{Rgba, RgbaImage};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::path::Path;
+
+const KMEANS_ITERATIONS: u32 = 20;
+
+// hex colors separated by commas or whitespaces, like "#fff,#000000"
+pub fn parse_palette(s: &str) -> PurrResult<Vec<Rgba<u8>>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|c| !c.is_empty())
+        .map(parse_hex_color)
+        .collect()
+}
+
+// palette file with one hex color per line
+pub fn open_palette<P: AsRef<Path>>(path: P) -> PurrResult<Vec<Rgba<u8>>> {
+    let content = fs::read_to_string(path)?;
+    parse_palette(&content)
+}
+
+// the palette color nearest to c in rgb, c may be far out of the rgb range with a
+// low alpha, so the distances are in i64
+pub fn nearest_color(palette: &[Rgba<u8>], c: [i32; 3]) -> Rgba<u8> {
+    let mut best = palette[0];
+    let mut best_d = i64::MAX;
+    for p in palette {
+        let dr = p.0[0] as i64 - c[0] as i64;
+        let dg = p.0[1] as i64 - c[1] as i64;
+        let db = p.0[2] as i64 - c[2] as i64;
+        let d = dr * dr + dg * dg + db * db;
+        if d < best_d {
+            best = *p;
+            best_d = d;
+        }
+    }
+    best
+}
+
+fn distance2(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1]) + (a[2] - b[2]) * (a[2] - b[2])
+}
+
+fn nearest_center(centers: &[[f64; 3]], p: &[f64; 3]) -> usize {
+    let mut best = 0;
+    for (j, c) in centers.iter().enumerate() {
+        if distance2(p, c) < distance2(p, &centers[best]) {
+            best = j;
+        }
+    }
+    best
+}
+
+// k main colors of the image by k-means, initialized by k-means++ with a fixed seed,
+// so that the same image always gives the same palette. less than k colors are
+// returned if the image does not have k distinct colors
+pub fn extract_palette(img: &RgbaImage, k: usize) -> Vec<Rgba<u8>> {
+    let pixels: Vec<[f64; 3]> = img
+        .pixels()
+        .map(|p| [p.0[0] as f64, p.0[1] as f64, p.0[2] as f64])
+        .collect();
+    if pixels.is_empty() || k == 0 {
+        return Vec::new();
+    }
+    let mut rng = SmallRng::seed_from_u64(0);
+
+    // k-means++, the next center is picked with the probability of the squared
+    // distance to the nearest center picked
+    let mut centers = vec![pixels[rng.gen_range(0, pixels.len())]];
+    let mut dist: Vec<f64> = pixels.iter().map(|p| distance2(p, &centers[0])).collect();
+    while centers.len() < k {
+        let total: f64 = dist.iter().sum();
+        if total == 0.0 {
+            break;
+        }
+        let mut t = rng.gen::<f64>() * total;
+        let mut next = pixels.len() - 1;
+        for (i, d) in dist.iter().enumerate() {
+            if t < *d {
+                next = i;
+                break;
+            }
+            t -= d;
+        }
+        let c = pixels[next];
+        for (i, p) in pixels.iter().enumerate() {
+            dist[i] = dist[i].min(distance2(p, &c));
+        }
+        centers.push(c);
+    }
+
+    // lloyd iterations
+    let mut assignment = vec![usize::MAX; pixels.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        let mut sums = vec![[0.0; 4]; centers.len()];
+        for (i, p) in pixels.iter().enumerate() {
+            let j = nearest_center(&centers, p);
+            if assignment[i] != j {
+                assignment[i] = j;
+                changed = true;
+            }
+            sums[j][0] += p[0];
+            sums[j][1] += p[1];
+            sums[j][2] += p[2];
+            sums[j][3] += 1.0;
+        }
+        if !changed {
+            break;
+        }
+        for (c, s) in centers.iter_mut().zip(sums.iter()) {
+            // an empty cluster keeps its center
+            if s[3] > 0.0 {
+                *c = [s[0] / s[3], s[1] / s[3], s[2] / s[3]];
+            }
+        }
+    }
+
+    centers
+        .iter()
+        .map(|c| {
+            Rgba([
+                c[0].round() as u8,
+                c[1].round() as u8,
+                c[2].round() as u8,
+                255,
+            ])
+        })
+        .collect()
+}
+
+// index of the color in the palette, alpha is ignored
+pub fn palette_index(palette: &[Rgba<u8>], c: &Rgba<u8>) -> Option<usize> {
+    palette.iter().position(|p| p.0[..3] == c.0[..3])
+}
+
+// css classes of the palette, c{index} for the fill of the shapes,
+// and the stroke of the beziers which are the only paths
+pub fn palette_css(palette: &[Rgba<u8>]) -> String {
+    let mut css = "".to_owned();
+    for (i, p) in palette.iter().enumerate() {
+        let hex = format!("#{:02X}{:02X}{:02X}", p.0[0], p.0[1], p.0[2]);
+        css += &format!(
+            ".c{}{{fill:{}}}path.c{}{{fill:none;stroke:{}}}",
+            i, hex, i, hex
+        );
+    }
+    css
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::PurrError;
+
+    #[test]
+    fn test_parse_palette() {
+        let palette = parse_palette("#fff,#000000 #FF8000\n").unwrap();
+        assert_eq!(
+            palette,
+            vec![
+                Rgba([255, 255, 255, 255]),
+                Rgba([0, 0, 0, 255]),
+                Rgba([255, 128, 0, 255]),
+            ]
+        );
+        match parse_palette("#fff,#ff") {
+            Err(PurrError::InvalidColor(c)) => assert_eq!(c, "#ff"),
+            _ => panic!("#ff is not a color"),
+        }
+        assert!(matches!(
+            parse_palette("#fff,#ggg"),
+            Err(PurrError::InvalidColor(_))
+        ));
+
+        let path = std::env::temp_dir().join(format!("purr-palette-{}.txt", std::process::id()));
+        fs::write(&path, "#102030\n\n#405060\n").unwrap();
+        let palette = open_palette(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            palette,
+            vec![Rgba([16, 32, 48, 255]), Rgba([64, 80, 96, 255])]
+        );
+        assert!(matches!(open_palette(&path), Err(PurrError::Io(_))));
+    }
+
+    #[test]
+    fn test_extract_palette() {
+        let red = Rgba([200, 20, 20, 255]);
+        let blue = Rgba([10, 40, 220, 255]);
+        let img = RgbaImage::from_fn(20, 10, |x, y| if (x + y) % 3 == 0 { red } else { blue });
+        let mut palette = extract_palette(&img, 2);
+        palette.sort_by_key(|c| c.0[0]);
+        assert_eq!(palette, vec![blue, red]);
+        // the image does not have more distinct colors
+        assert_eq!(extract_palette(&img, 5).len(), 2);
+        assert!(extract_palette(&img, 0).is_empty());
+        // the same image gives the same palette
+        assert_eq!(extract_palette(&img, 2), extract_palette(&img, 2));
+    }
+
+    #[test]
+    fn test_palette_css() {
+        let palette = vec![Rgba([255, 128, 0, 255]), Rgba([0, 0, 0, 255])];
+        assert_eq!(
+            palette_css(&palette),
+            ".c0{fill:#FF8000}path.c0{fill:none;stroke:#FF8000}\
+             .c1{fill:#000000}path.c1{fill:none;stroke:#000000}"
+        );
+        assert_eq!(palette_index(&palette, &Rgba([0, 0, 0, 128])), Some(1));
+        assert_eq!(palette_index(&palette, &Rgba([0, 0, 1, 255])), None);
+    }
+}
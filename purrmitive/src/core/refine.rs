@@ -0,0 +1,179 @@
+use crate::core::hill_climb;
+use crate::core::{PurrContext, PurrShape, PurrState};
+use log::debug;
+use std::sync::{Arc, RwLock};
+
+// revisit the states one at a time: the state is climbed on the image rendered without
+// it, as if it was drawn last, and the change is kept when the score of the whole image,
+// with the state at its original place, improves. returns the number of changed states
+pub fn refine_states<T: PurrShape>(
+    ctx: &mut PurrContext,
+    states: &mut [PurrState<T>],
+    age: u32,
+) -> usize {
+    let mut changed = 0;
+    // climbs on its own image, the current image is shared with the workers
+    let mut climb_ctx = ctx.clone();
+    climb_ctx.sampler = None;
+    // the states before i
+    let mut prefix = ctx.render::<T>(&[]);
+    for i in 0..states.len() {
+        let mut base = prefix.clone();
+        for s in &states[i + 1..] {
+            ctx.draw(&mut base, s);
+        }
+        climb_ctx.current_img = Arc::new(RwLock::new(base));
+        climb_ctx.rescore();
+
+        let mut state = states[i];
+        let lines = ctx.rasterize(&state.shape);
+        {
+            let cur = climb_ctx.current_img.read().unwrap();
+            let (score, sse) = climb_ctx.partial_score(&cur, &lines, state.color);
+            state.score = score;
+            state.sse = sse;
+        }
+        let climbed = hill_climb(&mut climb_ctx, state, age);
+        if climbed.score < state.score {
+            let mut img = prefix.clone();
+            ctx.draw(&mut img, &climbed);
+            for s in &states[i + 1..] {
+                ctx.draw(&mut img, s);
+            }
+            let (score, sse) = ctx.full_score(&img);
+            if score < ctx.score {
+                debug!("refine No.{}: {} -> {}", i, ctx.score, score);
+                states[i] = PurrState {
+                    score,
+                    sse,
+                    ..climbed
+                };
+                *ctx.current_img.write().unwrap() = img;
+                ctx.score = score;
+                ctx.sse = sse;
+                changed += 1;
+            }
+        }
+        ctx.draw(&mut prefix, &states[i]);
+    }
+    ctx.rng = climb_ctx.rng;
+    if changed > 0 {
+        // the residuals of the sampler are outdated
+        ctx.rescore();
+    }
+    changed
+}
+
+// remove the states whose removal makes the score of the whole image worse by less
+// than threshold, like those covered by the later states. the threshold bounds the
+// loss of all the removals together, each removal is compared with the score before
+// pruning. current_img and the score are updated, returns the number of removed states
+pub fn prune_states<T: PurrShape>(
+    ctx: &mut PurrContext,
+    states: &mut Vec<PurrState<T>>,
+    threshold: f64,
+) -> usize {
+    let total = states.len();
+    let base = ctx.score;
+    // the states before i
+    let mut prefix = ctx.render::<T>(&[]);
+    let mut i = 0;
+    while i < states.len() {
+        let mut img = prefix.clone();
+        for s in &states[i + 1..] {
+            ctx.draw(&mut img, s);
+        }
+        let (score, sse) = ctx.full_score(&img);
+        if score - base < threshold {
+            debug!("prune No.{}: {} -> {}", i, ctx.score, score);
+            states.remove(i);
+            *ctx.current_img.write().unwrap() = img;
+            ctx.score = score;
+            ctx.sse = sse;
+        } else {
+            ctx.draw(&mut prefix, &states[i]);
+            i += 1;
+        }
+    }
+    let removed = total - states.len();
+    if removed > 0 {
+        // the residuals of the sampler are outdated
+        ctx.rescore();
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::best_hill_climb;
+    use crate::graphics::{Point, Rectangle, Triangle};
+    use crate::{Rgba, RgbaImage};
+
+    fn test_context() -> PurrContext {
+        let img = RgbaImage::from_fn(24, 24, |x, y| {
+            Rgba([(x * 10) as u8, (y * 10) as u8, ((x ^ y) * 8) as u8, 255])
+        });
+        PurrContext::from_rgba(img, 24, 24, 128, None).with_seed(Some(5))
+    }
+
+    #[test]
+    fn test_refine_states() {
+        let mut ctx = test_context();
+        let mut states: Vec<PurrState<Triangle>> = Vec::new();
+        for _ in 0..8 {
+            // short climbs, so that there is something left to refine
+            let state = best_hill_climb(&mut ctx, 5, 1, 2);
+            ctx.add_state(&state);
+            states.push(state);
+        }
+        let mut changed = 0;
+        for _ in 0..3 {
+            let before = ctx.score;
+            changed += refine_states(&mut ctx, &mut states, 20);
+            assert!(ctx.score <= before);
+            // the current image is the rendering of the refined states
+            let img = ctx.render(&states);
+            assert!(*ctx.current_img.read().unwrap() == img);
+            assert_eq!(ctx.full_score(&img), (ctx.score, ctx.sse));
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn test_prune_states() {
+        // green on the left half, blue on the right half
+        let img = RgbaImage::from_fn(24, 24, |x, _| match x {
+            0..=11 => Rgba([0, 255, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        });
+        let mut ctx = PurrContext::from_rgba(img, 24, 24, 255, Some(Rgba([0, 0, 0, 255])));
+        let rect = |x, color| PurrState {
+            shape: Rectangle {
+                p: Point { x, y: 0 },
+                x: 11,
+                y: 23,
+            },
+            color,
+            ..PurrState::default()
+        };
+        // the red one is covered by the green one
+        let mut states = vec![
+            rect(0, Rgba([255, 0, 0, 255])),
+            rect(0, Rgba([0, 255, 0, 255])),
+            rect(12, Rgba([0, 0, 255, 255])),
+        ];
+        ctx.set_states(&states);
+        assert_eq!(ctx.sse, 0);
+        assert_eq!(prune_states(&mut ctx, &mut states, 1e-6), 1);
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].color, Rgba([0, 255, 0, 255]));
+        assert_eq!(ctx.sse, 0);
+
+        // removing either of the others costs less than the threshold, but removing both
+        // of them costs more
+        let score = ctx.full_score(&ctx.render(&states[..1])).0;
+        assert_eq!(prune_states(&mut ctx, &mut states, score * 1.2), 1);
+        assert_eq!(states.len(), 1);
+    }
+}
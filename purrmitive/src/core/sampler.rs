@@ -0,0 +1,185 @@
+use crate::core::{PurrMetric, WeightMap};
+use crate::graphics::Scanline;
+use crate::RgbaImage;
+use rand::Rng;
+
+// samples pixels with the probability proportional to their residual, the weighted
+// squared error between origin_img and current_img, so that new shapes are anchored
+// where the image is not reproduced well yet
+#[derive(Debug, Clone)]
+pub struct ResidualSampler {
+    w: u32,
+    h: u32,
+    residual: Vec<u64>,
+    // summed-area table of the residuals, (w + 1) x (h + 1) with a zero first row and column
+    table: Vec<u64>,
+    // only the pixels in the mask are sampled
+    mask: Option<Vec<bool>>,
+}
+
+impl ResidualSampler {
+    pub fn new(
+        origin_img: &RgbaImage,
+        current_img: &RgbaImage,
+        weights: Option<&WeightMap>,
+        metric: &PurrMetric,
+    ) -> Self {
+        let (w, h) = origin_img.dimensions();
+        let mut sampler = ResidualSampler {
+            w,
+            h,
+            residual: vec![0; (w * h) as usize],
+            table: vec![0; ((w + 1) * (h + 1)) as usize],
+            mask: None,
+        };
+        for y in 0..h {
+            for x in 0..w {
+                sampler.residual[(y * w + x) as usize] =
+                    residual_of(origin_img, current_img, weights, metric, x, y);
+            }
+        }
+        sampler.rebuild(0);
+        sampler
+    }
+
+    // restrict the samples to the pixels where the mask, in the size of w x h, is true
+    pub fn with_mask(mut self, mask: Vec<bool>) -> Self {
+        for (r, m) in self.residual.iter_mut().zip(mask.iter()) {
+            if !m {
+                *r = 0;
+            }
+        }
+        self.mask = Some(mask);
+        self.rebuild(0);
+        self
+    }
+
+    // update the residuals of the pixels covered by the lines, after they are drawn
+    pub fn update(
+        &mut self,
+        origin_img: &RgbaImage,
+        current_img: &RgbaImage,
+        lines: &Vec<Scanline>,
+        weights: Option<&WeightMap>,
+        metric: &PurrMetric,
+    ) {
+        let mut min_y = self.h;
+        for line in lines {
+            for x in line.x1..=line.x2 {
+                let i = (line.y * self.w + x) as usize;
+                self.residual[i] = match &self.mask {
+                    Some(mask) if !mask[i] => 0,
+                    _ => residual_of(origin_img, current_img, weights, metric, x, line.y),
+                };
+            }
+            min_y = std::cmp::min(min_y, line.y);
+        }
+        // rows above the lines are not affected
+        self.rebuild(min_y);
+    }
+
+    fn rebuild(&mut self, from_y: u32) {
+        let stride = (self.w + 1) as usize;
+        for y in from_y as usize..self.h as usize {
+            for x in 0..self.w as usize {
+                let i = (y + 1) * stride + x + 1;
+                self.table[i] = self.residual[y * self.w as usize + x]
+                    + self.table[i - 1]
+                    + self.table[i - stride]
+                    - self.table[i - stride - 1];
+            }
+        }
+    }
+
+    // a random pixel, uniformly if the image is reproduced exactly
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> (u32, u32) {
+        let stride = (self.w + 1) as usize;
+        let w = self.w as usize;
+        let total = self.table[self.h as usize * stride + w];
+        if total == 0 {
+            return (rng.gen_range(0, self.w), rng.gen_range(0, self.h));
+        }
+        let r = rng.gen_range(0, total);
+        // the first row whose cumulative sum exceeds r
+        let y = partition(self.h as usize, |y| self.table[(y + 1) * stride + w] <= r);
+        let r = r - self.table[y * stride + w];
+        // then the first pixel of the row
+        let x = partition(w, |x| {
+            self.table[(y + 1) * stride + x + 1] - self.table[y * stride + x + 1] <= r
+        });
+        (x as u32, y as u32)
+    }
+}
+
+// number of the leading elements of 0..n satisfying pred, which must be monotonic
+fn partition<F: Fn(usize) -> bool>(n: usize, pred: F) -> usize {
+    let mut lo = 0;
+    let mut hi = n;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+#[inline]
+fn residual_of(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    weights: Option<&WeightMap>,
+    metric: &PurrMetric,
+    x: u32,
+    y: u32,
+) -> u64 {
+    let e = metric.error(
+        origin_img.get_pixel(x, y),
+        x,
+        y,
+        current_img.get_pixel(x, y),
+    ) as u64;
+    match weights {
+        Some(m) => e * m.get(x, y) as u64,
+        None => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rgba;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sample_residual() {
+        let width = 13;
+        let height = 7;
+        let origin = RgbaImage::new(width, height);
+        let mut current = RgbaImage::new(width, height);
+        // only two pixels differ
+        current.put_pixel(3, 2, Rgba([10, 0, 0, 0]));
+        current.put_pixel(12, 6, Rgba([0, 10, 0, 0]));
+        let mut sampler = ResidualSampler::new(&origin, &current, None, &PurrMetric::default());
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let p = sampler.sample(&mut rng);
+            assert!(p == (3, 2) || p == (12, 6));
+        }
+
+        current.put_pixel(3, 2, Rgba([0, 0, 0, 0]));
+        let lines = vec![Scanline {
+            y: 2,
+            x1: 0,
+            x2: 5,
+            coverage: 255,
+        }];
+        sampler.update(&origin, &current, &lines, None, &PurrMetric::default());
+        for _ in 0..100 {
+            assert_eq!(sampler.sample(&mut rng), (12, 6));
+        }
+    }
+}
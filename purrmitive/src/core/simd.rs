@@ -0,0 +1,279 @@
+// integer fast paths of the scoring and the color computation over the raw rgba rows,
+// only built with the simd feature. the errors are computed with sse2 on x86_64, neon
+// on aarch64 and a scalar fallback elsewhere, the color computation is scalar. with the
+// feature the shapes are composited by alpha_compose_fixed, so the results are
+// identical to the reference implementations of algo
+use crate::alpha_compose_fixed;
+use crate::core::WeightMap;
+use crate::graphics::Scanline;
+use crate::{Rgba, RgbaImage};
+
+// the bytes of the pixels covered by the line, in an image of width w
+#[inline]
+fn row<'a>(data: &'a [u8], w: u32, line: &Scanline, channels: usize) -> &'a [u8] {
+    let start = (line.y * w + line.x1) as usize * channels;
+    let end = (line.y * w + line.x2 + 1) as usize * channels;
+    &data[start..end]
+}
+
+// like diff_partial_with_color, for the rgb metric
+pub fn diff_rows_with_color(
+    origin_img: &RgbaImage,
+    before_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    sse: u64,
+    color: Rgba<u8>,
+    weights: Option<&WeightMap>,
+) -> u64 {
+    let w = origin_img.width();
+    let mut total = sse;
+    for line in lines {
+        let origin = row(origin_img.as_raw(), w, line, 4);
+        let before = row(before_img.as_raw(), w, line, 4);
+        let weights = weights.map(|m| row(&m.data, w, line, 1));
+        let (after_sse, before_sse) = blend_errors(origin, before, weights, color);
+        // add first, total always contains the error of before
+        total += after_sse;
+        total -= before_sse;
+    }
+    total
+}
+
+// like least_squares_color in algo, None when all the pixels are weighted zero. there
+// is no simd here, only the scalar loop over the rows without the bounds checks of
+// get_pixel
+pub fn scalar_least_squares_rows(
+    origin_img: &RgbaImage,
+    current_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    alpha: u8,
+    weights: Option<&WeightMap>,
+) -> Option<[i32; 3]> {
+    let w = origin_img.width();
+    let a: i32 = 0x101 * 255 / alpha as i32;
+    let mut sums = [0i64; 3];
+    let mut count = 0;
+    for line in lines {
+        let origin = row(origin_img.as_raw(), w, line, 4);
+        let current = row(current_img.as_raw(), w, line, 4);
+        let weights = weights.map(|m| row(&m.data, w, line, 1));
+        for (i, (o, c)) in origin
+            .chunks_exact(4)
+            .zip(current.chunks_exact(4))
+            .enumerate()
+        {
+            let wt = weights.map_or(1, |w| w[i] as i64);
+            for ch in 0..3 {
+                let (o, c) = (o[ch] as i32, c[ch] as i32);
+                sums[ch] += ((o - c) * a + c * 0x101) as i64 * wt;
+            }
+            count += wt;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    Some([
+        (sums[0] / count) as i32 >> 8,
+        (sums[1] / count) as i32 >> 8,
+        (sums[2] / count) as i32 >> 8,
+    ])
+}
+
+// weighted squared errors of the origin pixels against the color composited onto
+// the before pixels, and against the before pixels
+#[cfg(target_arch = "x86_64")]
+fn blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    // sse2 is part of x86_64
+    unsafe { sse2_blend_errors(origin, before, weights, color) }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    // neon is part of aarch64
+    unsafe { neon_blend_errors(origin, before, weights, color) }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    scalar_blend_errors(origin, before, weights, color)
+}
+
+fn scalar_blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    let mut sums = (0, 0);
+    for (i, (o, b)) in origin
+        .chunks_exact(4)
+        .zip(before.chunks_exact(4))
+        .enumerate()
+    {
+        let wt = weights.map_or(1, |w| w[i] as u64);
+        let after = alpha_compose_fixed(&Rgba([b[0], b[1], b[2], b[3]]), &color);
+        sums.0 += error(o, &after.0) * wt;
+        sums.1 += error(o, b) * wt;
+    }
+    sums
+}
+
+#[inline]
+fn error(o: &[u8], p: &[u8]) -> u64 {
+    let mut e = 0;
+    for c in 0..4 {
+        let d = o[c] as i32 - p[c] as i32;
+        e += d * d;
+    }
+    e as u64
+}
+
+// the blend of alpha_compose_fixed is b * (255 - a) + f * a for the rgb channels, the alpha
+// channel gets 0 * b + 255 * 255, so that it is 255 after the division.
+// all the values fit in u16
+fn blend_terms(color: Rgba<u8>) -> ([u16; 8], [u16; 8]) {
+    let a = color.0[3] as u16;
+    let c = color.0;
+    let inv = [255 - a, 255 - a, 255 - a, 0, 255 - a, 255 - a, 255 - a, 0];
+    let add = [
+        c[0] as u16 * a,
+        c[1] as u16 * a,
+        c[2] as u16 * a,
+        255 * 255,
+        c[0] as u16 * a,
+        c[1] as u16 * a,
+        c[2] as u16 * a,
+        255 * 255,
+    ];
+    (inv, add)
+}
+
+// two pixels per iteration, in u16 lanes
+#[cfg(target_arch = "x86_64")]
+unsafe fn sse2_blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    use std::arch::x86_64::*;
+
+    // squared errors of the two pixels times their weights, in the two u64 lanes
+    #[inline]
+    unsafe fn weighted_error(o: __m128i, p: __m128i, wt: __m128i) -> __m128i {
+        let d = _mm_sub_epi16(o, p);
+        // r * r + g * g and b * b + a * a of each pixel
+        let s = _mm_madd_epi16(d, d);
+        let s = _mm_add_epi32(s, _mm_shuffle_epi32(s, 0b10_11_00_01));
+        // the pixel sums are in lanes 0 and 2
+        _mm_mul_epu32(s, wt)
+    }
+
+    let (inv, add) = blend_terms(color);
+    let inv = _mm_loadu_si128(inv.as_ptr() as *const __m128i);
+    let add = _mm_loadu_si128(add.as_ptr() as *const __m128i);
+    let zero = _mm_setzero_si128();
+    let one = _mm_set1_epi16(1);
+    let mut after_acc = zero;
+    let mut before_acc = zero;
+    let n = origin.len() / 4;
+    let mut i = 0;
+    while i + 2 <= n {
+        let o = _mm_loadl_epi64(origin.as_ptr().add(i * 4) as *const __m128i);
+        let b = _mm_loadl_epi64(before.as_ptr().add(i * 4) as *const __m128i);
+        let o = _mm_unpacklo_epi8(o, zero);
+        let b = _mm_unpacklo_epi8(b, zero);
+        let x = _mm_add_epi16(_mm_mullo_epi16(b, inv), add);
+        // x / 255 like div255
+        let after = _mm_srli_epi16(
+            _mm_add_epi16(_mm_add_epi16(x, one), _mm_srli_epi16(x, 8)),
+            8,
+        );
+        let wt = match weights {
+            Some(w) => _mm_setr_epi32(w[i] as i32, 0, w[i + 1] as i32, 0),
+            None => _mm_setr_epi32(1, 0, 1, 0),
+        };
+        after_acc = _mm_add_epi64(after_acc, weighted_error(o, after, wt));
+        before_acc = _mm_add_epi64(before_acc, weighted_error(o, b, wt));
+        i += 2;
+    }
+
+    let mut lanes = [0u64; 4];
+    _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, after_acc);
+    _mm_storeu_si128(lanes.as_mut_ptr().add(2) as *mut __m128i, before_acc);
+    let tail = scalar_blend_errors(
+        &origin[i * 4..],
+        &before[i * 4..],
+        weights.map(|w| &w[i..]),
+        color,
+    );
+    (lanes[0] + lanes[1] + tail.0, lanes[2] + lanes[3] + tail.1)
+}
+
+// two pixels per iteration, in u16 lanes
+#[cfg(target_arch = "aarch64")]
+unsafe fn neon_blend_errors(
+    origin: &[u8],
+    before: &[u8],
+    weights: Option<&[u8]>,
+    color: Rgba<u8>,
+) -> (u64, u64) {
+    use std::arch::aarch64::*;
+
+    // squared errors of the two pixels
+    #[inline]
+    unsafe fn errors(o: uint16x8_t, p: uint16x8_t) -> (u64, u64) {
+        let d = vsubq_s16(vreinterpretq_s16_u16(o), vreinterpretq_s16_u16(p));
+        let lo = vmull_s16(vget_low_s16(d), vget_low_s16(d));
+        let hi = vmull_high_s16(d, d);
+        (vaddvq_s32(lo) as u64, vaddvq_s32(hi) as u64)
+    }
+
+    let (inv, add) = blend_terms(color);
+    let inv = vld1q_u16(inv.as_ptr());
+    let add = vld1q_u16(add.as_ptr());
+    let one = vdupq_n_u16(1);
+    let mut sums = (0, 0);
+    let n = origin.len() / 4;
+    let mut i = 0;
+    while i + 2 <= n {
+        let o = vmovl_u8(vld1_u8(origin.as_ptr().add(i * 4)));
+        let b = vmovl_u8(vld1_u8(before.as_ptr().add(i * 4)));
+        let x = vmlaq_u16(add, b, inv);
+        // x / 255 like div255
+        let after = vshrq_n_u16::<8>(vaddq_u16(vaddq_u16(x, one), vshrq_n_u16::<8>(x)));
+        let (w0, w1) = match weights {
+            Some(w) => (w[i] as u64, w[i + 1] as u64),
+            None => (1, 1),
+        };
+        let (a0, a1) = errors(o, after);
+        let (b0, b1) = errors(o, b);
+        sums.0 += a0 * w0 + a1 * w1;
+        sums.1 += b0 * w0 + b1 * w1;
+        i += 2;
+    }
+
+    let tail = scalar_blend_errors(
+        &origin[i * 4..],
+        &before[i * 4..],
+        weights.map(|w| &w[i..]),
+        color,
+    );
+    (sums.0 + tail.0, sums.1 + tail.1)
+}
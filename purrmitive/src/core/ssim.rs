@@ -0,0 +1,189 @@
+// structural similarity, computed on the rgb channels over non-overlapping windows.
+// it is not the standard ssim, which slides an 11x11 gaussian window over each pixel,
+// the tiled windows are what lets a candidate only recompute the windows it touches.
+// the values are close to but do not match the ones of other ssim tools
+use crate::graphics::{Blend, Scanline};
+use crate::{Rgba, RgbaImage};
+use std::collections::BTreeMap;
+
+// size of the square windows, the windows at the right and bottom borders may be smaller
+pub const SSIM_WINDOW: u32 = 8;
+
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+// what the search minimizes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PurrObjective {
+    // root mean square error in the color space of the metric
+    Rms,
+    // 1 - mean ssim
+    Ssim,
+}
+
+impl PurrObjective {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rms" => Some(PurrObjective::Rms),
+            "ssim" => Some(PurrObjective::Ssim),
+            _ => None,
+        }
+    }
+}
+
+// sums of the pixels of a window, a is the origin and b the approximation
+#[derive(Debug, Clone, Copy, Default)]
+struct WindowSums {
+    n: i64,
+    a: [i64; 3],
+    aa: [i64; 3],
+    b: [i64; 3],
+    bb: [i64; 3],
+    ab: [i64; 3],
+}
+
+impl WindowSums {
+    fn of(origin: &RgbaImage, img: &RgbaImage, wx: u32, wy: u32) -> Self {
+        let (w, h) = origin.dimensions();
+        let mut sums = WindowSums::default();
+        for y in wy * SSIM_WINDOW..std::cmp::min((wy + 1) * SSIM_WINDOW, h) {
+            for x in wx * SSIM_WINDOW..std::cmp::min((wx + 1) * SSIM_WINDOW, w) {
+                sums.add(origin.get_pixel(x, y), img.get_pixel(x, y), 1);
+                sums.n += 1;
+            }
+        }
+        sums
+    }
+
+    // sign is 1 to add the pixel and -1 to remove it, n is left unchanged
+    #[inline]
+    fn add(&mut self, a: &Rgba<u8>, b: &Rgba<u8>, sign: i64) {
+        for c in 0..3 {
+            let va = a.0[c] as i64;
+            let vb = b.0[c] as i64;
+            self.a[c] += sign * va;
+            self.aa[c] += sign * va * va;
+            self.b[c] += sign * vb;
+            self.bb[c] += sign * vb * vb;
+            self.ab[c] += sign * va * vb;
+        }
+    }
+
+    // ssim of the window, averaged over the channels
+    fn ssim(&self) -> f64 {
+        let n = self.n as f64;
+        let mut total = 0.0;
+        for c in 0..3 {
+            let ma = self.a[c] as f64 / n;
+            let mb = self.b[c] as f64 / n;
+            let va = self.aa[c] as f64 / n - ma * ma;
+            let vb = self.bb[c] as f64 / n - mb * mb;
+            let cov = self.ab[c] as f64 / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+        }
+        total / 3.0
+    }
+}
+
+// mean ssim of the two images, each window is weighted by its number of pixels,
+// 1 means identical
+pub fn ssim(origin_img: &RgbaImage, img: &RgbaImage) -> f64 {
+    let (w, h) = origin_img.dimensions();
+    let mut total = 0.0;
+    for wy in 0..h.div_ceil(SSIM_WINDOW) {
+        for wx in 0..w.div_ceil(SSIM_WINDOW) {
+            let sums = WindowSums::of(origin_img, img, wx, wy);
+            total += sums.ssim() * sums.n as f64;
+        }
+    }
+    total / (w * h) as f64
+}
+
+// score of the ssim objective, lower is better like the rms score
+pub fn dssim_full(origin_img: &RgbaImage, current_img: &RgbaImage) -> f64 {
+    1.0 - ssim(origin_img, current_img)
+}
+
+// like diff_partial_with_color, only the windows touched by the lines are recomputed
+pub fn dssim_partial_with_color(
+    origin_img: &RgbaImage,
+    before_img: &RgbaImage,
+    lines: &Vec<Scanline>,
+    score: f64,
+    color: Rgba<u8>,
+    blend: &Blend,
+) -> f64 {
+    if lines.is_empty() {
+        return score;
+    }
+    let (w, h) = origin_img.dimensions();
+    let stride = w.div_ceil(SSIM_WINDOW);
+    // window index -> (sums before, sums after), ordered so that the float sum below
+    // is the same in every run
+    let mut windows: BTreeMap<u32, (WindowSums, WindowSums)> = BTreeMap::new();
+    for line in lines {
+        let color = line.covered(&color);
+        let wy = line.y / SSIM_WINDOW;
+        for wx in line.x1 / SSIM_WINDOW..=line.x2 / SSIM_WINDOW {
+            let (_, after) = windows.entry(wy * stride + wx).or_insert_with(|| {
+                let sums = WindowSums::of(origin_img, before_img, wx, wy);
+                (sums, sums)
+            });
+            let x1 = std::cmp::max(line.x1, wx * SSIM_WINDOW);
+            let x2 = std::cmp::min(line.x2, (wx + 1) * SSIM_WINDOW - 1);
+            for x in x1..=x2 {
+                let origin = origin_img.get_pixel(x, line.y);
+                let before = before_img.get_pixel(x, line.y);
+                after.add(origin, before, -1);
+                after.add(origin, &blend.compose(before, &color), 1);
+            }
+        }
+    }
+
+    let mut total = (1.0 - score) * (w * h) as f64;
+    for (before, after) in windows.values() {
+        total += (after.ssim() - before.ssim()) * before.n as f64;
+    }
+    1.0 - total / (w * h) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dssim_partial_with_color() {
+        let width = 20;
+        let height = 19;
+        let mut origin = RgbaImage::new(width, height);
+        for (x, y, p) in origin.enumerate_pixels_mut() {
+            *p = Rgba([(x * 12) as u8, (y * 13) as u8, ((x + y) * 6) as u8, 255]);
+        }
+        let mut current = RgbaImage::new(width, height);
+        for p in current.pixels_mut() {
+            *p = Rgba([128, 128, 128, 255]);
+        }
+        assert!((ssim(&origin, &origin) - 1.0).abs() < 1e-9);
+
+        let color = Rgba([200, 30, 90, 128]);
+        let lines: Vec<Scanline> = (3..17)
+            .map(|y| Scanline {
+                y,
+                x1: 5,
+                x2: 18,
+                coverage: 255,
+            })
+            .collect();
+        let score = dssim_full(&origin, &current);
+        let partial =
+            dssim_partial_with_color(&origin, &current, &lines, score, color, &Blend::srgb());
+        for line in &lines {
+            for x in line.x1..=line.x2 {
+                let p = Blend::srgb().compose(current.get_pixel(x, line.y), &color);
+                current.put_pixel(x, line.y, p);
+            }
+        }
+        assert!((partial - dssim_full(&origin, &current)).abs() < 1e-9);
+    }
+}
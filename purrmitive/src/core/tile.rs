@@ -0,0 +1,575 @@
+// tiled processing for large images, the image is split into overlapping tiles which
+// are optimized independently, and the shapes are stitched with the offsets of the tiles
+use super::{cut_out, save_steps, states_svg, svg_begin, svg_end};
+use crate::core::*;
+use crate::RgbaImage;
+use crossbeam_channel::unbounded;
+use log::{info, warn};
+use std::sync::{Arc, RwLock};
+use threadpool::ThreadPool;
+
+// a region of the image with its own model, the shapes are in the coordinates of the
+// tile. only the core of the tile is shown, the rest overlaps the neighbors
+pub struct PurrTile<T, M> {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    // [x1, y1, x2, y2) in the image
+    pub core: [u32; 4],
+    pub states: Vec<PurrState<T>>,
+    // taken by the thread stepping the tile
+    model: Option<M>,
+}
+
+impl<T: PurrShape, M: PurrModel<T>> PurrTile<T, M> {
+    pub fn context(&self) -> &PurrContext {
+        self.model.as_ref().unwrap().context()
+    }
+
+    fn context_mut(&mut self) -> &mut PurrContext {
+        self.model.as_mut().unwrap().context_mut()
+    }
+}
+
+// each tile gets shape_number shapes, the tiles are stepped in parallel. the current
+// image of the model is stitched from the tiles by run, refine and prune
+pub struct PurrTiledRunner<T: PurrShape, M = PurrSearchModel> {
+    pub shape_number: u32,
+    pub thread_number: u32,
+    pub tile_size: u32,
+    pub overlap: u32,
+    // shapes placed over the borders of the tiles after they are stitched
+    pub seam_shapes: u32,
+    pub tiles: Vec<PurrTile<T, M>>,
+    // in the coordinates of the image, drawn over the tiles
+    pub seams: Vec<PurrState<T>>,
+    pub on_step: Option<OnStep<T>>,
+    pool: Option<ThreadPool>,
+}
+
+impl<T, M> PurrModelRunner for PurrTiledRunner<T, M>
+where
+    T: 'static + PurrShape,
+    M: 'static + PurrModel<T> + Clone + Send,
+{
+    type M = M;
+    fn init(&mut self, model: &mut Self::M) {
+        self.stop();
+        self.tiles.clear();
+        self.seams.clear();
+        let ctx = model.context();
+        let xs = spans(ctx.w, self.tile_size, self.overlap);
+        let ys = spans(ctx.h, self.tile_size, self.overlap);
+        for [y, h, y1, y2] in &ys {
+            for [x, w, x1, x2] in &xs {
+                let mut tile_ctx = ctx.crop(*x, *y, *w, *h);
+                tile_ctx.rng = ctx.stream_rng(self.tiles.len() as u64 + 1);
+                let mut tile_model = model.clone();
+                *tile_model.context_mut() = tile_ctx;
+                self.tiles.push(PurrTile {
+                    x: *x,
+                    y: *y,
+                    w: *w,
+                    h: *h,
+                    core: [*x1, *y1, *x2, *y2],
+                    states: Vec::new(),
+                    model: Some(tile_model),
+                });
+            }
+        }
+        info!("{}x{} tiles", xs.len(), ys.len());
+        self.pool = Some(ThreadPool::new(self.thread_number as usize));
+    }
+
+    fn step(&mut self, _model: &mut Self::M) {
+        self.step_tiles(None);
+    }
+
+    fn stop(&mut self) {
+        // all the tiles are back after each step
+        self.pool = None;
+    }
+
+    fn run(&mut self, model: &mut Self::M, score: f64) {
+        self.init(model);
+
+        if score > 0.0 && score < 1.0 {
+            while self.step_tiles(Some(score)) {}
+        } else {
+            for _ in 0..self.shape_number {
+                self.step_tiles(None);
+            }
+        }
+
+        self.stop();
+        self.stitch(model);
+        if self.seam_shapes > 0 {
+            self.smooth_seams(model);
+        }
+    }
+
+    fn run_pyramid(&mut self, model: &mut Self::M, score: f64, levels: u32) {
+        if levels > 1 {
+            warn!("levels are not supported with tiles, ignored");
+        }
+        self.run(model, score);
+    }
+
+    // the seams are kept as they are
+    fn refine(&mut self, model: &mut Self::M, age: u32) -> usize {
+        let mut changed = 0;
+        for tile in self.tiles.iter_mut() {
+            let mut states = std::mem::take(&mut tile.states);
+            changed += refine_states(tile.context_mut(), &mut states, age);
+            tile.states = states;
+        }
+        self.stitch(model);
+        changed
+    }
+
+    // the threshold applies to the score of each tile
+    fn prune(&mut self, model: &mut Self::M, threshold: f64) -> usize {
+        let mut removed = 0;
+        for tile in self.tiles.iter_mut() {
+            let mut states = std::mem::take(&mut tile.states);
+            removed += prune_states(tile.context_mut(), &mut states, threshold);
+            tile.states = states;
+        }
+        self.stitch(model);
+        removed
+    }
+
+    // idx counts the steps of the tiles, then the seam shapes
+    fn get_svg(&self, context: &PurrContext, idx: usize) -> String {
+        let palette = context.palette.as_deref().map(|p| p.as_slice());
+        let end = idx.saturating_add(1);
+        let mut output = svg_begin(context);
+        output += "<defs>";
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let [x1, y1, x2, y2] = tile.core;
+            output += &format!(
+                "<clipPath id=\"tile{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath>",
+                i,
+                x1,
+                y1,
+                x2 - x1,
+                y2 - y1
+            );
+        }
+        output += "</defs>";
+        for (i, tile) in self.tiles.iter().enumerate() {
+            output += &format!(
+                "<g clip-path=\"url(#tile{})\"><g transform=\"translate({} {})\">",
+                i,
+                tile.x as f64 + 0.5,
+                tile.y as f64 + 0.5
+            );
+            for state in tile.states.iter().take(end) {
+                output += &state.to_svg(palette);
+            }
+            output += "</g></g>";
+        }
+        output += "<g transform=\"scale(1) translate(0.5 0.5)\">";
+        for state in self.seams.iter().take(end.saturating_sub(self.steps())) {
+            output += &state.to_svg(palette);
+        }
+        output += "</g>";
+        output += svg_end(context);
+        output
+    }
+
+    // like get_svg, the steps of the tiles, then the seam shapes
+    fn save(&self, context: &PurrContext, output: &str) -> PurrResult<()> {
+        let n = self.steps() + self.seams.len();
+        save_steps(
+            context,
+            output,
+            n,
+            n,
+            |i| self.get_svg(context, i),
+            |i| self.rasterize(context, i),
+        )
+    }
+
+    fn get_last_shape(&self) -> String {
+        if let Some(s) = self.seams.last() {
+            return s.to_svg(None);
+        }
+        match self
+            .tiles
+            .last()
+            .and_then(|t| t.states.last().map(|s| (t, s)))
+        {
+            Some((t, s)) => format!(
+                "<g transform=\"translate({} {})\">{}</g>",
+                t.x,
+                t.y,
+                s.to_svg(None)
+            ),
+            None => "".to_string(),
+        }
+    }
+}
+
+impl<T, M> PurrTiledRunner<T, M>
+where
+    T: 'static + PurrShape,
+    M: 'static + PurrModel<T> + Clone + Send,
+{
+    // step the tiles in parallel, the tiles reaching the score are skipped.
+    // returns false if no tile is stepped
+    fn step_tiles(&mut self, score: Option<f64>) -> bool {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return false,
+        };
+        let (tx, rx) = unbounded();
+        let mut jobs = 0;
+        for (i, tile) in self.tiles.iter_mut().enumerate() {
+            if let Some(s) = score {
+                if tile.context().score <= s {
+                    continue;
+                }
+            }
+            let mut model = tile.model.take().unwrap();
+            let tx = tx.clone();
+            pool.execute(move || {
+                let state: PurrState<T> = model.step();
+                model.add_state(&state);
+                tx.send((i, model, state)).unwrap();
+            });
+            jobs += 1;
+        }
+
+        for _ in 0..jobs {
+            let (i, model, state) = rx.recv().unwrap();
+            let tile = &mut self.tiles[i];
+            tile.states.push(state);
+            if let Some(f) = &mut self.on_step {
+                f(tile.states.len(), state, model.context());
+            }
+            tile.model = Some(model);
+        }
+        jobs > 0
+    }
+
+    // copy the cores of the tiles into the current image of the model, with the seams
+    // drawn on top
+    pub fn stitch(&self, model: &mut M) {
+        let ctx = model.context_mut();
+        {
+            let mut cur = ctx.current_img.write().unwrap();
+            for tile in &self.tiles {
+                let img = tile.context().current_img.read().unwrap();
+                let [x1, y1, x2, y2] = tile.core;
+                for y in y1..y2 {
+                    for x in x1..x2 {
+                        cur.put_pixel(x, y, *img.get_pixel(x - tile.x, y - tile.y));
+                    }
+                }
+            }
+            for state in &self.seams {
+                ctx.draw(&mut cur, state);
+            }
+        }
+        ctx.rescore();
+    }
+
+    // place the seam shapes with the model itself, anchored within half of the overlap
+    // around the inner borders of the cores
+    fn smooth_seams(&mut self, model: &mut M) {
+        let band = std::cmp::max(self.overlap / 2, 1);
+        let ctx = model.context_mut();
+        let (w, h) = (ctx.w, ctx.h);
+        let mut mask = vec![false; (w * h) as usize];
+        for tile in &self.tiles {
+            let [x1, y1, x2, y2] = tile.core;
+            for y in y1..y2 {
+                for x in x1..x2 {
+                    mask[(y * w + x) as usize] = (x1 > 0 && x < x1 + band)
+                        || (x2 < w && x + band >= x2)
+                        || (y1 > 0 && y < y1 + band)
+                        || (y2 < h && y + band >= y2);
+                }
+            }
+        }
+        let sampler = {
+            let cur = ctx.current_img.read().unwrap();
+            ResidualSampler::new(&ctx.origin_img, &cur, ctx.weights.as_deref(), &ctx.metric)
+                .with_mask(mask)
+        };
+        let sampler = ctx.sampler.replace(Arc::new(RwLock::new(sampler)));
+
+        for _ in 0..self.seam_shapes {
+            let state: PurrState<T> = model.step();
+            model.add_state(&state);
+            self.seams.push(state);
+            if let Some(f) = &mut self.on_step {
+                f(self.seams.len(), state, model.context());
+            }
+        }
+
+        let ctx = model.context_mut();
+        ctx.sampler = sampler;
+        ctx.rescore();
+    }
+
+    // number of steps of the tiles
+    fn steps(&self) -> usize {
+        self.tiles.iter().map(|t| t.states.len()).max().unwrap_or(0)
+    }
+
+    // nsvg ignores the clip paths, so each tile is rasterized on its own and its core
+    // is pasted, then the seams are drawn on top. idx counts the steps like get_svg
+    fn rasterize(&self, context: &PurrContext, idx: usize) -> PurrResult<RgbaImage> {
+        let s = context.scale;
+        let end = idx.saturating_add(1);
+        let seam_states =
+            &self.seams[..std::cmp::min(end.saturating_sub(self.steps()), self.seams.len())];
+        let mut seams = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">",
+            context.w, context.h
+        );
+        seams += "<g transform=\"scale(1) translate(0.5 0.5)\">";
+        for state in seam_states {
+            seams += &state.to_svg(None);
+        }
+        seams += "</g></svg>";
+        // nsvg composites in srgb, with the linear blend the shapes are drawn like in
+        // the search, and the seams directly onto the image
+        let linear = context.blend.tables().is_some();
+        let seams = if linear {
+            None
+        } else {
+            Some(rasterize_svg(&seams, s)?)
+        };
+        let (w, h) = match &seams {
+            Some(seams) => seams.dimensions(),
+            None => ((context.w as f32 * s) as u32, (context.h as f32 * s) as u32),
+        };
+
+        let mut img = RgbaImage::new(w, h);
+        for tile in &self.tiles {
+            let states = &tile.states[..std::cmp::min(end, tile.states.len())];
+            let tile_img = if linear {
+                tile.context().render_scaled(states, s)
+            } else {
+                rasterize_svg(&states_svg(tile.context(), states), s)?
+            };
+            let ox = (tile.x as f32 * s) as u32;
+            let oy = (tile.y as f32 * s) as u32;
+            let [x1, y1, x2, y2] = tile.core;
+            let x2 = std::cmp::min((x2 as f32 * s) as u32, img.width());
+            let y2 = std::cmp::min((y2 as f32 * s) as u32, img.height());
+            for y in (y1 as f32 * s) as u32..y2 {
+                for x in (x1 as f32 * s) as u32..x2 {
+                    if x - ox < tile_img.width() && y - oy < tile_img.height() {
+                        img.put_pixel(x, y, *tile_img.get_pixel(x - ox, y - oy));
+                    }
+                }
+            }
+        }
+        match seams {
+            Some(seams) => image::imageops::overlay(&mut img, &seams, 0, 0),
+            None => {
+                for state in seam_states {
+                    context.draw_shape(&mut img, &state.shape.scale(s as f64), &state.color);
+                }
+            }
+        }
+        cut_out(context, &mut img);
+        Ok(img)
+    }
+}
+
+impl<T: 'static + PurrShape, M> PurrTiledRunner<T, M> {
+    pub fn new(
+        shape_number: u32,
+        thread_number: u32,
+        tile_size: u32,
+        overlap: u32,
+        seam_shapes: u32,
+        on_step: Option<OnStep<T>>,
+    ) -> Self {
+        PurrTiledRunner {
+            shape_number,
+            thread_number,
+            tile_size,
+            overlap,
+            seam_shapes,
+            tiles: Vec::new(),
+            seams: Vec::new(),
+            on_step,
+            pool: None,
+        }
+    }
+}
+
+// [start, length, core start, core end] of the tiles along a side of len pixels.
+// the cores split the overlaps in half, the first and last ones reach the edges
+fn spans(len: u32, size: u32, overlap: u32) -> Vec<[u32; 4]> {
+    let stride = std::cmp::max(size.saturating_sub(overlap), 1);
+    let n = std::cmp::max(len.saturating_sub(overlap).div_ceil(stride), 1);
+    (0..n)
+        .map(|i| {
+            let start = i * stride;
+            let core_start = if i == 0 { 0 } else { start + overlap / 2 };
+            let core_end = if i == n - 1 {
+                len
+            } else {
+                start + stride + overlap / 2
+            };
+            [
+                start,
+                std::cmp::min(start + size, len) - start,
+                core_start,
+                core_end,
+            ]
+        })
+        .collect()
+}
+
+#[macro_export]
+macro_rules! tiled_runner {
+    ($x: ty, $shape_number: expr, $thread_number: expr, $tile_size: expr, $overlap: expr, $seam_shapes: expr, $cb_creator: expr) => {{
+        let cb = Some($cb_creator());
+        Box::new(PurrTiledRunner::<$x>::new(
+            $shape_number,
+            $thread_number,
+            $tile_size,
+            $overlap,
+            $seam_shapes,
+            cb,
+        ))
+    }};
+}
+
+#[macro_export]
+macro_rules! tiled_model_runner {
+    ($mode: expr, $sn: expr, $tn: expr, $ts: expr, $ov: expr, $ss: expr, $cb_creator: expr) => {{
+        tiled_model_runner!($mode, $sn, $tn, $ts, $ov, $ss, $cb_creator, PurrSearchModel)
+    }};
+    ($mode: expr, $sn: expr, $tn: expr, $ts: expr, $ov: expr, $ss: expr, $cb_creator: expr, $model: ty) => {{
+        let runner: PurrResult<Box<dyn PurrModelRunner<M = $model> + Sync + Send>> = match $mode {
+            0 => Ok(tiled_runner!(Combo, $sn, $tn, $ts, $ov, $ss, $cb_creator)),
+            1 => Ok(tiled_runner!(
+                Triangle,
+                $sn,
+                $tn,
+                $ts,
+                $ov,
+                $ss,
+                $cb_creator
+            )),
+            2 => Ok(tiled_runner!(
+                Rectangle,
+                $sn,
+                $tn,
+                $ts,
+                $ov,
+                $ss,
+                $cb_creator
+            )),
+            3 => Ok(tiled_runner!(Ellipse, $sn, $tn, $ts, $ov, $ss, $cb_creator)),
+            4 => Ok(tiled_runner!(Circle, $sn, $tn, $ts, $ov, $ss, $cb_creator)),
+            5 => Ok(tiled_runner!(
+                RotatedRectangle,
+                $sn,
+                $tn,
+                $ts,
+                $ov,
+                $ss,
+                $cb_creator
+            )),
+            6 => Ok(tiled_runner!(
+                Quadratic,
+                $sn,
+                $tn,
+                $ts,
+                $ov,
+                $ss,
+                $cb_creator
+            )),
+            7 => Ok(tiled_runner!(
+                RotatedEllipse,
+                $sn,
+                $tn,
+                $ts,
+                $ov,
+                $ss,
+                $cb_creator
+            )),
+            8 => Ok(tiled_runner!(Polygon, $sn, $tn, $ts, $ov, $ss, $cb_creator)),
+            m => Err(PurrError::UnsupportedMode(m as i32)),
+        };
+        runner
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Triangle;
+    use crate::Rgba;
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(spans(100, 128, 32), vec![[0, 100, 0, 100]]);
+        assert_eq!(
+            spans(300, 128, 32),
+            vec![[0, 128, 0, 112], [96, 128, 112, 208], [192, 108, 208, 300]]
+        );
+        // the cores cover the side exactly, within the tiles
+        for len in 1..400 {
+            let s = spans(len, 64, 16);
+            let mut end = 0;
+            for [start, l, core_start, core_end] in s {
+                assert_eq!(core_start, end);
+                assert!(start <= core_start && core_end <= start + l && core_start < core_end);
+                end = core_end;
+            }
+            assert_eq!(end, len);
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let img = RgbaImage::from_fn(48, 40, |x, y| {
+            Rgba([(x * 5) as u8, (y * 6) as u8, ((x + y) * 3) as u8, 255])
+        });
+        let ctx = PurrContext::from_rgba(img, 48, 48, 128, None).with_seed(Some(3));
+        let mut model = PurrHillClimbModel::new(ctx, 10, 2, 5);
+        let mut runner: PurrTiledRunner<Triangle, PurrHillClimbModel> =
+            PurrTiledRunner::new(3, 2, 32, 8, 2, None);
+        runner.run(&mut model, 0.0);
+        let n = runner.steps() + runner.seams.len();
+        assert_eq!(n, 5);
+
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        // a longer file is overwritten, not kept at its end
+        let svg = dir.join(format!("purr-tiled-{}.svg", id));
+        std::fs::write(&svg, "x".repeat(1 << 16)).unwrap();
+        runner.save(&model.context, svg.to_str().unwrap()).unwrap();
+        let out = std::fs::read_to_string(&svg).unwrap();
+        std::fs::remove_file(&svg).unwrap();
+        assert_eq!(out, runner.get_svg(&model.context, n - 1));
+        assert!(out.ends_with("</svg>"));
+
+        // a png for each step
+        let pattern = dir.join(format!("purr-tiled-{}-{{}}.png", id));
+        runner
+            .save(&model.context, pattern.to_str().unwrap())
+            .unwrap();
+        for i in 1..=n {
+            let frame = dir.join(format!("purr-tiled-{}-{}.png", id, i));
+            let frame_img = image::open(&frame).unwrap();
+            std::fs::remove_file(&frame).unwrap();
+            assert_eq!(
+                frame_img.to_rgba8(),
+                runner.rasterize(&model.context, i - 1).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::core::PurrResult;
+use crate::{clamp, div255};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma, RgbaImage};
+use std::path::Path;
+
+// minimum weight of the generated maps, so that flat areas are still reproduced
+const FOCUS_FLOOR: f64 = 32.0;
+
+// how to derive the weight map from the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PurrFocus {
+    // gradient magnitude (sobel)
+    Edges,
+    // local variance
+    Variance,
+}
+
+impl PurrFocus {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "edges" => Some(PurrFocus::Edges),
+            "variance" => Some(PurrFocus::Variance),
+            _ => None,
+        }
+    }
+}
+
+// per-pixel importance, the squared error of each pixel is scaled by its weight
+#[derive(Debug, Clone)]
+pub struct WeightMap {
+    pub w: u32,
+    pub h: u32,
+    pub data: Vec<u8>,
+    pub total: u64,
+}
+
+impl WeightMap {
+    pub fn new(img: &GrayImage) -> Self {
+        let (w, h) = img.dimensions();
+        let data = img.as_raw().clone();
+        let total = data.iter().map(|x| *x as u64).sum::<u64>();
+        WeightMap {
+            w,
+            h,
+            data,
+            // avoid dividing by zero when all the weights are zero
+            total: std::cmp::max(total, 1),
+        }
+    }
+
+    // grayscale weight image, it will be resized to w x h
+    pub fn from_image(img: &DynamicImage, w: u32, h: u32) -> Self {
+        let gray = img.resize_exact(w, h, FilterType::Triangle).into_luma8();
+        WeightMap::new(&gray)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, w: u32, h: u32) -> PurrResult<Self> {
+        let img = image::open(path)?;
+        Ok(WeightMap::from_image(&img, w, h))
+    }
+
+    // derive the weights from the image itself
+    pub fn from_focus(img: &RgbaImage, focus: PurrFocus) -> Self {
+        let (w, h) = img.dimensions();
+        // spread the importance to the neighborhood, shapes cover areas not pixels
+        let radius = std::cmp::max(std::cmp::max(w, h) / 64, 2);
+        let values = match focus {
+            PurrFocus::Edges => {
+                let edges = sobel(img);
+                let blurred = image::imageops::blur(&to_gray(&edges, w, h), radius as f32);
+                blurred.pixels().map(|p| p.0[0] as f64).collect()
+            }
+            PurrFocus::Variance => local_variance(img, radius),
+        };
+        let max = values.iter().cloned().fold(0.0, f64::max);
+        let mut gray = GrayImage::new(w, h);
+        for (i, p) in gray.pixels_mut().enumerate() {
+            let v = if max > 0.0 { values[i] / max } else { 0.0 };
+            *p = Luma([(FOCUS_FLOOR + (255.0 - FOCUS_FLOOR) * v) as u8]);
+        }
+        WeightMap::new(&gray)
+    }
+
+    pub fn resize(&self, w: u32, h: u32) -> Self {
+        let gray = GrayImage::from_raw(self.w, self.h, self.data.clone()).unwrap();
+        WeightMap::new(&image::imageops::resize(&gray, w, h, FilterType::Triangle))
+    }
+
+    pub fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> Self {
+        let gray = GrayImage::from_raw(self.w, self.h, self.data.clone()).unwrap();
+        WeightMap::new(&image::imageops::crop_imm(&gray, x, y, w, h).to_image())
+    }
+
+    // the weights scaled by the mask, in the same size, 255 keeps the weight
+    pub fn masked(&self, mask: &GrayImage) -> Self {
+        let data = self.data.iter().zip(mask.as_raw().iter());
+        let data = data
+            .map(|(w, m)| div255(*w as u32 * *m as u32) as u8)
+            .collect();
+        WeightMap::new(&GrayImage::from_raw(self.w, self.h, data).unwrap())
+    }
+
+    #[inline]
+    pub fn get(&self, x: u32, y: u32) -> u32 {
+        self.data[(y * self.w + x) as usize] as u32
+    }
+}
+
+fn luma(img: &RgbaImage) -> Vec<f64> {
+    img.pixels()
+        .map(|p| (p.0[0] as f64 * 299.0 + p.0[1] as f64 * 587.0 + p.0[2] as f64 * 114.0) / 1000.0)
+        .collect()
+}
+
+// scale values to 0-255
+fn to_gray(values: &[f64], w: u32, h: u32) -> GrayImage {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    let mut gray = GrayImage::new(w, h);
+    for (i, p) in gray.pixels_mut().enumerate() {
+        let v = if max > 0.0 {
+            values[i] / max * 255.0
+        } else {
+            0.0
+        };
+        *p = Luma([v as u8]);
+    }
+    gray
+}
+
+// gradient magnitude of the luma
+fn sobel(img: &RgbaImage) -> Vec<f64> {
+    let (w, h) = img.dimensions();
+    let l = luma(img);
+    let at = |x: i32, y: i32| {
+        let x = clamp(x, 0, w as i32 - 1) as u32;
+        let y = clamp(y, 0, h as i32 - 1) as u32;
+        l[(y * w + x) as usize]
+    };
+    let mut values = vec![0.0; (w * h) as usize];
+    for y in 0..h as i32 {
+        for x in 0..w as i32 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            values[(y as u32 * w + x as u32) as usize] = (gx * gx + gy * gy).sqrt();
+        }
+    }
+    values
+}
+
+// standard deviation of the luma in a (2r+1)x(2r+1) window, using summed-area tables
+fn local_variance(img: &RgbaImage, r: u32) -> Vec<f64> {
+    let (w, h) = img.dimensions();
+    let l = luma(img);
+    let stride = (w + 1) as usize;
+    let mut sum = vec![0.0; stride * (h + 1) as usize];
+    let mut sum2 = vec![0.0; stride * (h + 1) as usize];
+    for y in 0..h as usize {
+        for x in 0..w as usize {
+            let v = l[y * w as usize + x];
+            let i = (y + 1) * stride + x + 1;
+            sum[i] = v + sum[i - 1] + sum[i - stride] - sum[i - stride - 1];
+            sum2[i] = v * v + sum2[i - 1] + sum2[i - stride] - sum2[i - stride - 1];
+        }
+    }
+    let area = |t: &Vec<f64>, x0: usize, y0: usize, x1: usize, y1: usize| {
+        t[y1 * stride + x1] - t[y0 * stride + x1] - t[y1 * stride + x0] + t[y0 * stride + x0]
+    };
+    let mut values = vec![0.0; (w * h) as usize];
+    for y in 0..h {
+        for x in 0..w {
+            let x0 = x.saturating_sub(r) as usize;
+            let y0 = y.saturating_sub(r) as usize;
+            let x1 = std::cmp::min(x + r + 1, w) as usize;
+            let y1 = std::cmp::min(y + r + 1, h) as usize;
+            let n = ((x1 - x0) * (y1 - y0)) as f64;
+            let mean = area(&sum, x0, y0, x1, y1) / n;
+            let var = area(&sum2, x0, y0, x1, y1) / n - mean * mean;
+            values[(y * w + x) as usize] = var.max(0.0).sqrt();
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rgba;
+
+    // black on the left half, white on the right half
+    fn edge_image() -> RgbaImage {
+        RgbaImage::from_fn(32, 16, |x, _| match x {
+            0..=15 => Rgba([0, 0, 0, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        })
+    }
+
+    #[test]
+    fn test_sobel_and_variance() {
+        let img = edge_image();
+        for values in [sobel(&img), local_variance(&img, 2)].iter() {
+            // flat areas have no gradient or variance, the edge has
+            assert_eq!(values[8 * 32], 0.0);
+            assert_eq!(values[8 * 32 + 31], 0.0);
+            assert!(values[8 * 32 + 15] > 0.0);
+            assert!(values[8 * 32 + 16] > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_from_focus() {
+        let img = edge_image();
+        for focus in [PurrFocus::Edges, PurrFocus::Variance].iter() {
+            let map = WeightMap::from_focus(&img, *focus);
+            assert_eq!((map.w, map.h), (32, 16));
+            // the edge weighs more than the flat corner, which keeps the floor
+            assert!(map.get(16, 8) > map.get(0, 8));
+            assert_eq!(map.get(0, 8), FOCUS_FLOOR as u32);
+            assert_eq!(map.total, map.data.iter().map(|x| *x as u64).sum());
+        }
+        // a flat image is weighted evenly by the floor
+        let flat = RgbaImage::from_pixel(8, 8, Rgba([90, 90, 90, 255]));
+        let map = WeightMap::from_focus(&flat, PurrFocus::Edges);
+        assert!(map.data.iter().all(|x| *x as f64 == FOCUS_FLOOR));
+    }
+
+    #[test]
+    fn test_resize_and_mask() {
+        let map = WeightMap::new(&GrayImage::from_fn(8, 4, |x, _| Luma([x as u8 * 30])));
+        let resized = map.resize(16, 8);
+        assert_eq!((resized.w, resized.h), (16, 8));
+        assert_eq!(resized.data.len(), 16 * 8);
+        let uniform = WeightMap::new(&GrayImage::from_pixel(8, 4, Luma([100])));
+        assert!(uniform.resize(5, 3).data.iter().all(|x| *x == 100));
+
+        // opaque keeps the weight, transparent clears it, and zero stays zero
+        let mask = GrayImage::from_fn(8, 4, |_, y| Luma([if y < 2 { 255 } else { 0 }]));
+        let masked = map.masked(&mask);
+        for y in 0..4 {
+            for x in 0..8 {
+                let expected = if y < 2 { map.get(x, y) } else { 0 };
+                assert_eq!(masked.get(x, y), expected);
+            }
+        }
+        assert_eq!(masked.get(0, 0), 0);
+        assert_eq!(masked.total, (0..8).map(|x| x * 30 * 2).sum::<u64>());
+    }
+}
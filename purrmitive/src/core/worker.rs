@@ -1,24 +1,21 @@
-use crate::core::{PurrHillClimbModel, PurrModel, PurrShape, PurrState};
+use crate::core::{PurrModel, PurrShape, PurrState};
 use crossbeam_channel::{Receiver, Sender};
 
 pub enum PurrWorkerCmd {
     Start,
-    UpdateScore(f64),
+    // score and sse of the current image
+    UpdateScore(f64, u64),
     End,
 }
 
-pub struct PurrWorker<T: PurrShape> {
-    model: PurrHillClimbModel,
+pub struct PurrWorker<T: PurrShape, M: PurrModel<T>> {
+    model: M,
     rx: Receiver<PurrWorkerCmd>,
     tx: Sender<PurrState<T>>,
 }
 
-impl<T: PurrShape> PurrWorker<T> {
-    pub fn new(
-        model: PurrHillClimbModel,
-        rx: Receiver<PurrWorkerCmd>,
-        tx: Sender<PurrState<T>>,
-    ) -> Self {
+impl<T: PurrShape, M: PurrModel<T>> PurrWorker<T, M> {
+    pub fn new(model: M, rx: Receiver<PurrWorkerCmd>, tx: Sender<PurrState<T>>) -> Self {
         PurrWorker { model, rx, tx }
     }
 
@@ -29,8 +26,10 @@ impl<T: PurrShape> PurrWorker<T> {
                 PurrWorkerCmd::Start => {
                     self.work();
                 }
-                PurrWorkerCmd::UpdateScore(s) => {
-                    self.model.context.score = s;
+                PurrWorkerCmd::UpdateScore(score, sse) => {
+                    let ctx = self.model.context_mut();
+                    ctx.score = score;
+                    ctx.sse = sse;
                 }
                 PurrWorkerCmd::End => {
                     return;
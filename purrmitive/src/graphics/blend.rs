@@ -0,0 +1,137 @@
+use crate::{compose_srgb, Rgba};
+use std::sync::Arc;
+
+// linear light values have 12 bits, so that the dark srgb values are kept apart
+const LINEAR_MAX: u32 = 4095;
+
+// srgb to linear light and back, 12-bit linear values
+#[derive(Debug)]
+pub struct LinearTables {
+    pub to_linear: Vec<u16>,
+    pub to_srgb: Vec<u8>,
+}
+
+impl LinearTables {
+    fn new() -> Self {
+        let to_linear = (0..256)
+            .map(|i| {
+                let c = i as f64 / 255.0;
+                let l = if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                };
+                (l * LINEAR_MAX as f64).round() as u16
+            })
+            .collect();
+        let to_srgb = (0..=LINEAR_MAX)
+            .map(|i| {
+                let l = i as f64 / LINEAR_MAX as f64;
+                let c = if l <= 0.003_130_8 {
+                    l * 12.92
+                } else {
+                    1.055 * l.powf(1.0 / 2.4) - 0.055
+                };
+                (c * 255.0).round() as u8
+            })
+            .collect();
+        LinearTables { to_linear, to_srgb }
+    }
+
+    // srgb of a linear value, which is clamped to the range
+    #[inline]
+    pub fn srgb(&self, l: i32) -> u8 {
+        self.to_srgb[l.max(0).min(LINEAR_MAX as i32) as usize]
+    }
+}
+
+// how the colors of the shapes are composited onto the image, in srgb bytes like most
+// svg renderers, or in linear light
+#[derive(Debug, Clone, Default)]
+pub struct Blend {
+    linear: Option<Arc<LinearTables>>,
+}
+
+impl Blend {
+    pub fn srgb() -> Self {
+        Blend { linear: None }
+    }
+
+    pub fn linear() -> Self {
+        Blend {
+            linear: Some(Arc::new(LinearTables::new())),
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "srgb" => Some(Blend::srgb()),
+            "linear" => Some(Blend::linear()),
+            _ => None,
+        }
+    }
+
+    // the tables of the linear blend, None for srgb
+    #[inline]
+    pub fn tables(&self) -> Option<&LinearTables> {
+        self.linear.as_deref()
+    }
+
+    #[inline]
+    pub fn compose(&self, bg: &Rgba<u8>, fg: &Rgba<u8>) -> Rgba<u8> {
+        let t = match &self.linear {
+            Some(t) => t,
+            None => return compose_srgb(bg, fg),
+        };
+        let a = fg.0[3] as u32;
+        let blend = |b: u8, f: u8| {
+            let l = (t.to_linear[b as usize] as u32 * (255 - a)
+                + t.to_linear[f as usize] as u32 * a
+                + 127)
+                / 255;
+            t.to_srgb[l as usize]
+        };
+        Rgba([
+            blend(bg.0[0], fg.0[0]),
+            blend(bg.0[1], fg.0[1]),
+            blend(bg.0[2], fg.0[2]),
+            255,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linear_compose() {
+        let t = LinearTables::new();
+        for i in 0..256 {
+            assert_eq!(t.to_srgb[t.to_linear[i] as usize] as usize, i);
+        }
+
+        let blend = Blend::linear();
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        // opaque and transparent colors are exact
+        assert_eq!(
+            blend.compose(&black, &Rgba([10, 20, 30, 255])).0[..3],
+            [10, 20, 30]
+        );
+        assert_eq!(
+            blend
+                .compose(&Rgba([10, 20, 30, 255]), &Rgba([0, 0, 0, 0]))
+                .0[..3],
+            [10, 20, 30]
+        );
+        // half white onto black is half the light, brighter than the srgb middle
+        let half = blend.compose(&black, &Rgba([255, 255, 255, 128]));
+        assert_eq!(half.0[0], 188);
+        assert_eq!(
+            Blend::srgb().compose(&black, &Rgba([255, 255, 255, 128])).0[0],
+            128
+        );
+        assert_eq!(blend.compose(&white, &Rgba([0, 0, 0, 128])).0[0], 187);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::core::PurrShape;
-use crate::graphics::{Ellipse, Point, Scanline, Shape};
+use crate::graphics::{scale_length, Ellipse, Point, Scanline, Shape};
 use crate::{Rgba, RgbaImage};
 use rand::{Rng, RngCore, SeedableRng};
 
@@ -13,9 +13,7 @@ impl Default for Circle {
 }
 
 impl Shape for Circle {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
-        let x = rng.gen_range(0, w as i32);
-        let y = rng.gen_range(0, h as i32);
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, _w: u32, _h: u32, rng: &mut T) -> Self {
         let r = rng.gen_range(0, 32) + 1;
 
         Circle(Ellipse {
@@ -41,13 +39,26 @@ impl Shape for Circle {
             _ => unreachable!(),
         }
     }
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
-        self.0.rasterize(w, h)
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        self.0.rasterize_into(w, h, lines)
     }
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
         self.0.draw(img, color)
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        let r = scale_length(self.0.rx, (sx + sy) / 2.0);
+        Circle(Ellipse {
+            o: self.0.o.transform(sx, sy),
+            rx: r,
+            ry: r,
+        })
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        self.0.outline(points)
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         self.0.to_svg(attr)
     }
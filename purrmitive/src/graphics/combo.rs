@@ -25,16 +25,16 @@ impl Default for Combo {
 }
 
 impl Shape for Combo {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, w: u32, h: u32, rng: &mut T) -> Self {
         match rng.gen_range(0, 8) {
-            0 => Combo::Triangle(Triangle::random(w, h, rng)),
-            1 => Combo::Ellipse(Ellipse::random(w, h, rng)),
-            2 => Combo::Rectangle(Rectangle::random(w, h, rng)),
-            3 => Combo::RotatedRectangle(RotatedRectangle::random(w, h, rng)),
-            4 => Combo::Circle(Circle::random(w, h, rng)),
-            5 => Combo::Quadratic(Quadratic::random(w, h, rng)),
-            6 => Combo::RotatedEllipse(RotatedEllipse::random(w, h, rng)),
-            7 => Combo::Polygon(Polygon::random(w, h, rng)),
+            0 => Combo::Triangle(Triangle::random_at(x, y, w, h, rng)),
+            1 => Combo::Ellipse(Ellipse::random_at(x, y, w, h, rng)),
+            2 => Combo::Rectangle(Rectangle::random_at(x, y, w, h, rng)),
+            3 => Combo::RotatedRectangle(RotatedRectangle::random_at(x, y, w, h, rng)),
+            4 => Combo::Circle(Circle::random_at(x, y, w, h, rng)),
+            5 => Combo::Quadratic(Quadratic::random_at(x, y, w, h, rng)),
+            6 => Combo::RotatedEllipse(RotatedEllipse::random_at(x, y, w, h, rng)),
+            7 => Combo::Polygon(Polygon::random_at(x, y, w, h, rng)),
             _ => unreachable!(),
         }
     }
@@ -51,16 +51,28 @@ impl Shape for Combo {
             Combo::Polygon(s) => s.mutate(w, h, rng),
         }
     }
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
         match self {
-            Combo::Triangle(s) => s.rasterize(w, h),
-            Combo::Ellipse(s) => s.rasterize(w, h),
-            Combo::Rectangle(s) => s.rasterize(w, h),
-            Combo::RotatedRectangle(s) => s.rasterize(w, h),
-            Combo::Circle(s) => s.rasterize(w, h),
-            Combo::Quadratic(s) => s.rasterize(w, h),
-            Combo::RotatedEllipse(s) => s.rasterize(w, h),
-            Combo::Polygon(s) => s.rasterize(w, h),
+            Combo::Triangle(s) => s.rasterize_into(w, h, lines),
+            Combo::Ellipse(s) => s.rasterize_into(w, h, lines),
+            Combo::Rectangle(s) => s.rasterize_into(w, h, lines),
+            Combo::RotatedRectangle(s) => s.rasterize_into(w, h, lines),
+            Combo::Circle(s) => s.rasterize_into(w, h, lines),
+            Combo::Quadratic(s) => s.rasterize_into(w, h, lines),
+            Combo::RotatedEllipse(s) => s.rasterize_into(w, h, lines),
+            Combo::Polygon(s) => s.rasterize_into(w, h, lines),
+        }
+    }
+    fn rasterize_coverage_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        match self {
+            Combo::Triangle(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::Ellipse(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::Rectangle(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::RotatedRectangle(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::Circle(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::Quadratic(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::RotatedEllipse(s) => s.rasterize_coverage_into(w, h, lines),
+            Combo::Polygon(s) => s.rasterize_coverage_into(w, h, lines),
         }
     }
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
@@ -76,6 +88,32 @@ impl Shape for Combo {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        match self {
+            Combo::Triangle(x) => Combo::Triangle(x.transform(sx, sy)),
+            Combo::Ellipse(x) => Combo::Ellipse(x.transform(sx, sy)),
+            Combo::Rectangle(x) => Combo::Rectangle(x.transform(sx, sy)),
+            Combo::RotatedRectangle(x) => Combo::RotatedRectangle(x.transform(sx, sy)),
+            Combo::Circle(x) => Combo::Circle(x.transform(sx, sy)),
+            Combo::Quadratic(x) => Combo::Quadratic(x.transform(sx, sy)),
+            Combo::RotatedEllipse(x) => Combo::RotatedEllipse(x.transform(sx, sy)),
+            Combo::Polygon(x) => Combo::Polygon(x.transform(sx, sy)),
+        }
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        match self {
+            Combo::Triangle(s) => s.outline(points),
+            Combo::Ellipse(s) => s.outline(points),
+            Combo::Rectangle(s) => s.outline(points),
+            Combo::RotatedRectangle(s) => s.outline(points),
+            Combo::Circle(s) => s.outline(points),
+            Combo::Quadratic(s) => s.outline(points),
+            Combo::RotatedEllipse(s) => s.outline(points),
+            Combo::Polygon(s) => s.outline(points),
+        }
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         match self {
             Combo::Triangle(s) => s.to_svg(attr),
@@ -1,7 +1,7 @@
 use crate::clamp;
 use crate::core::PurrShape;
 use crate::graphics::point::*;
-use crate::graphics::raster::{rasterize_ellipse, rasterize_rotated_ellipse};
+use crate::graphics::raster::{ellipse_outline, rasterize_ellipse, rasterize_rotated_ellipse};
 use crate::graphics::scanline::*;
 use crate::graphics::Shape;
 use crate::{Rgba, RgbaImage};
@@ -57,9 +57,7 @@ impl Ellipse {
 }
 
 impl Shape for Ellipse {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
-        let x = rng.gen_range(0, w as i32);
-        let y = rng.gen_range(0, h as i32);
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, _w: u32, _h: u32, rng: &mut T) -> Self {
         let rx = rng.gen_range(0, 32) + 1;
         let ry = rng.gen_range(0, 32) + 1;
 
@@ -84,16 +82,10 @@ impl Shape for Ellipse {
         }
     }
 
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
-        let lines = rasterize_ellipse(&self.o, self.rx, self.ry);
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
+        rasterize_ellipse(&self.o, self.rx, self.ry, lines);
+        crop_lines(lines, w, h);
     }
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
         let (w, h) = img.dimensions();
@@ -103,6 +95,25 @@ impl Shape for Ellipse {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        Ellipse {
+            o: self.o.transform(sx, sy),
+            rx: scale_length(self.rx, sx),
+            ry: scale_length(self.ry, sy),
+        }
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        ellipse_outline(
+            self.o.x as f64,
+            self.o.y as f64,
+            self.rx as f64,
+            self.ry as f64,
+            0.0,
+            points,
+        );
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         format!(
             "<ellipse {} cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" />",
@@ -135,10 +146,10 @@ impl Default for RotatedEllipse {
 }
 
 impl Shape for RotatedEllipse {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, w: u32, h: u32, rng: &mut T) -> Self {
         RotatedEllipse {
             degree: rng.gen_range(0, 360),
-            ellipse: Ellipse::random(w, h, rng),
+            ellipse: Ellipse::random_at(x, y, w, h, rng),
         }
     }
     fn mutate<T: SeedableRng + RngCore>(&mut self, w: u32, h: u32, rng: &mut T) {
@@ -160,24 +171,19 @@ impl Shape for RotatedEllipse {
         }
     }
 
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
         let angle = self.degree as f64 * std::f64::consts::PI / 180.0;
-        let lines = rasterize_rotated_ellipse(
+        rasterize_rotated_ellipse(
             &self.ellipse.o,
             self.ellipse.rx,
             self.ellipse.ry,
             angle,
             w,
             h,
+            lines,
         );
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+        crop_lines(lines, w, h);
     }
 
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
@@ -188,6 +194,30 @@ impl Shape for RotatedEllipse {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        let s = (sx + sy) / 2.0;
+        RotatedEllipse {
+            degree: self.degree,
+            ellipse: Ellipse {
+                o: self.ellipse.o.transform(sx, sy),
+                rx: scale_length(self.ellipse.rx, s),
+                ry: scale_length(self.ellipse.ry, s),
+            },
+        }
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        let e = &self.ellipse;
+        ellipse_outline(
+            e.o.x as f64,
+            e.o.y as f64,
+            e.rx as f64,
+            e.ry as f64,
+            self.degree as f64,
+            points,
+        );
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         format!(
 		"<g transform=\"translate({} {}) rotate({}) scale({} {})\"><ellipse {} cx=\"0\" cy=\"0\" rx=\"1\" ry=\"1\" /></g>",
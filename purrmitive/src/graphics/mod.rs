@@ -1,3 +1,4 @@
+mod blend;
 mod circle;
 mod combo;
 mod ellipse;
@@ -10,6 +11,7 @@ mod scanline;
 mod triangle;
 
 use crate::{Rgba, RgbaImage};
+pub use blend::*;
 pub use circle::*;
 pub use combo::*;
 pub use ellipse::*;
@@ -21,12 +23,56 @@ pub use rectangle::*;
 pub use scanline::*;
 pub use triangle::*;
 
+use rand::Rng;
 pub use rand::{RngCore, SeedableRng};
 
 pub trait Shape {
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline>;
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self;
+    // replace the content of lines with the scanlines of the shape, so that the
+    // buffer is reused
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>);
+    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
+        let mut lines = Vec::new();
+        self.rasterize_into(w, h, &mut lines);
+        lines
+    }
+    // like rasterize_into, edge pixels are partly covered by the exact area of the
+    // outline, see rasterize_outline
+    fn rasterize_coverage_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>)
+    where
+        Self: Sized,
+    {
+        rasterize_outline(self, w, h, lines)
+    }
+    // append the closed outline of the shape as drawn by to_svg, curves are flattened
+    // into segments shorter than the error of a tenth of a pixel
+    fn outline(&self, points: &mut Vec<(f64, f64)>);
+    // random shape anchored at (x, y)
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, w: u32, h: u32, rng: &mut T) -> Self;
+    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self
+    where
+        Self: Sized,
+    {
+        let x = rng.gen_range(0, w as i32);
+        let y = rng.gen_range(0, h as i32);
+        Self::random_at(x, y, w, h, rng)
+    }
     fn mutate<T: SeedableRng + RngCore>(&mut self, w: u32, h: u32, rng: &mut T);
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>);
+    fn draw_with(&self, img: &mut RgbaImage, color: &Rgba<u8>, blend: &Blend) {
+        let (w, h) = img.dimensions();
+        for line in self.rasterize(w, h) {
+            line.draw_with(img, color, blend);
+        }
+    }
+    // the shape in an image scaled by sx and sy, the coordinates are rounded, the
+    // lengths of rotated shapes are scaled by the mean of sx and sy
+    fn transform(&self, sx: f64, sy: f64) -> Self;
+    // the shape in an image scaled by s
+    fn scale(&self, s: f64) -> Self
+    where
+        Self: Sized,
+    {
+        self.transform(s, s)
+    }
     fn to_svg(&self, attr: &str) -> String;
 }
@@ -13,6 +13,19 @@ impl Point {
             y: (self.y as f64 * x) as i32,
         }
     }
+
+    // the point scaled by sx and sy, rounded to the nearest pixel
+    pub fn transform(self, sx: f64, sy: f64) -> Self {
+        Self {
+            x: (self.x as f64 * sx).round() as i32,
+            y: (self.y as f64 * sy).round() as i32,
+        }
+    }
+}
+
+// a length scaled by s, rounded and at least 1
+pub fn scale_length(l: u32, s: f64) -> u32 {
+    std::cmp::max((l as f64 * s).round() as u32, 1)
 }
 impl ops::Add for Point {
     type Output = Self;
@@ -61,10 +61,8 @@ impl Default for Polygon {
 }
 
 impl Shape for Polygon {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
+    fn random_at<T: SeedableRng + RngCore>(x0: i32, y0: i32, w: u32, h: u32, rng: &mut T) -> Self {
         let mut polygon = Polygon::default();
-        let x0 = rng.gen_range(0, w as i32);
-        let y0 = rng.gen_range(0, h as i32);
         polygon.points[0].x = x0;
         polygon.points[0].y = y0;
         for i in 1..4 {
@@ -104,21 +102,10 @@ impl Shape for Polygon {
         self.clockwise();
     }
 
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
-        let points = self
-            .points
-            .iter()
-            .map(|p| p.clone())
-            .collect::<Vec<Point>>();
-        let lines = rasterize_polygon(&points, w, h);
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
+        rasterize_polygon(&self.points, w, h, lines);
+        crop_lines(lines, w, h);
     }
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
         let (w, h) = img.dimensions();
@@ -128,6 +115,20 @@ impl Shape for Polygon {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        let mut polygon = *self;
+        for p in polygon.points.iter_mut() {
+            *p = p.transform(sx, sy);
+        }
+        polygon
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        for p in &self.points {
+            points.push((p.x as f64, p.y as f64));
+        }
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         let mut p = format!("<polygon {} points=\"", attr);
         let points_str: String = self
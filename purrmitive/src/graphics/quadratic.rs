@@ -1,7 +1,7 @@
 use crate::clamp;
 use crate::core::PurrShape;
-use crate::graphics::raster::rasterize_quad_bezier;
-use crate::graphics::{Point, Scanline, Shape};
+use crate::graphics::raster::{quad_bezier_stroke_outline, rasterize_quad_bezier, with_rows};
+use crate::graphics::{crop_lines, Point, Scanline, Shape};
 use crate::{Rgba, RgbaImage};
 use rand::{Rng, RngCore, SeedableRng};
 use rand_distr::StandardNormal;
@@ -39,9 +39,7 @@ impl Quadratic {
 }
 
 impl Shape for Quadratic {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
-        let px = rng.gen_range(0, w as i32);
-        let py = rng.gen_range(0, h as i32);
+    fn random_at<T: SeedableRng + RngCore>(px: i32, py: i32, w: u32, h: u32, rng: &mut T) -> Self {
         let p0 = Point { x: px, y: py };
         let p1 = Point {
             x: px + rng.gen_range(-20, 20),
@@ -103,16 +101,16 @@ impl Shape for Quadratic {
         }
     }
 
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
-        let lines = rasterize_quadratic(self, w, h);
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
+        rasterize_quadratic(self, w, h, lines);
+        crop_lines(lines, w, h);
+    }
+
+    // the stroke is half a pixel wide in svg
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        let f = |p: Point| (p.x as f64, p.y as f64);
+        quad_bezier_stroke_outline(f(self.p0), f(self.p1), f(self.p2), 0.5, points);
     }
 
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
@@ -123,6 +121,14 @@ impl Shape for Quadratic {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        Quadratic {
+            p0: self.p0.transform(sx, sy),
+            p1: self.p1.transform(sx, sy),
+            p2: self.p2.transform(sx, sy),
+        }
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         let attr = attr.replace("fill", "stroke");
         format!(
@@ -132,7 +138,8 @@ impl Shape for Quadratic {
     }
 }
 
-fn rasterize_quadratic(q: &Quadratic, w: u32, h: u32) -> Vec<Scanline> {
+// the scanlines are appended to lines
+fn rasterize_quadratic(q: &Quadratic, w: u32, h: u32, lines: &mut Vec<Scanline>) {
     let mut ymin = std::i32::MAX;
     let mut ymax = std::i32::MIN;
     for p in &[q.p0, q.p1, q.p2] {
@@ -145,10 +152,6 @@ fn rasterize_quadratic(q: &Quadratic, w: u32, h: u32) -> Vec<Scanline> {
     }
     let range = (ymax - ymin) as usize;
 
-    let mut buf_lhs: Vec<i32> = vec![std::i32::MAX; range + 2];
-    let mut buf_rhs: Vec<i32> = vec![std::i32::MIN; range + 2];
-    let mut scanlines = Vec::new();
-
     let x0 = q.p0.x;
     let y0 = q.p0.y;
     let x1 = q.p1.x;
@@ -156,33 +159,22 @@ fn rasterize_quadratic(q: &Quadratic, w: u32, h: u32) -> Vec<Scanline> {
     let x2 = q.p2.x;
     let y2 = q.p2.y;
 
-    rasterize_quad_bezier(
-        x0,
-        y0,
-        x1,
-        y1,
-        x2,
-        y2,
-        &mut buf_lhs,
-        &mut buf_rhs,
-        &mut scanlines,
-        w,
-        h,
-        ymin,
-    );
-    for i in 0..range {
-        let y = i as i32 + ymin;
-        if y >= 0 && y < h as i32 {
-            if buf_lhs[i] >= 0 {
-                scanlines.push(Scanline {
-                    y: clamp(y as u32, 0, h - 1),
-                    x1: clamp(buf_lhs[i], 0, w as i32 - 1) as u32,
-                    x2: clamp(buf_rhs[i], 0, w as i32 - 1) as u32,
-                });
+    with_rows(range + 2, |buf_lhs, buf_rhs| {
+        rasterize_quad_bezier(x0, y0, x1, y1, x2, y2, buf_lhs, buf_rhs, lines, w, h, ymin);
+        for i in 0..range {
+            let y = i as i32 + ymin;
+            if y >= 0 && y < h as i32 {
+                if buf_lhs[i] >= 0 {
+                    lines.push(Scanline {
+                        y: clamp(y as u32, 0, h - 1),
+                        x1: clamp(buf_lhs[i], 0, w as i32 - 1) as u32,
+                        x2: clamp(buf_rhs[i], 0, w as i32 - 1) as u32,
+                        coverage: 255,
+                    });
+                }
             }
         }
-    }
-    scanlines
+    });
 }
 
 impl PurrShape for Quadratic {}
@@ -1,11 +1,266 @@
 use crate::clamp;
-use crate::graphics::{Point, Scanline};
+use crate::graphics::{Point, Scanline, Shape};
+use std::cell::RefCell;
 
-// rasterize polygon
+thread_local! {
+    // the left and right x of each row, reused by the rasterizations of the thread
+    static ROWS: RefCell<(Vec<i32>, Vec<i32>)> = const { RefCell::new((Vec::new(), Vec::new())) };
+    // the outline of the shape and the area deltas of the rows of its bounding box,
+    // reused by the coverage rasterizations of the thread
+    static OUTLINE: RefCell<Vec<(f64, f64)>> = const { RefCell::new(Vec::new()) };
+    static AREAS: RefCell<Vec<f32>> = const { RefCell::new(Vec::new()) };
+}
+
+// run f with the row buffers of the thread, reset for n rows
+pub fn with_rows<F: FnOnce(&mut Vec<i32>, &mut Vec<i32>)>(n: usize, f: F) {
+    ROWS.with(|rows| {
+        let (buf_lhs, buf_rhs) = &mut *rows.borrow_mut();
+        buf_lhs.clear();
+        buf_lhs.resize(n, std::i32::MAX);
+        buf_rhs.clear();
+        buf_rhs.resize(n, std::i32::MIN);
+        f(buf_lhs, buf_rhs)
+    })
+}
+
+// replace the content of lines with the scanlines of the outline of the shape, see
+// Shape::outline. the coverage of a pixel is the area of the outline inside it, runs
+// of pixels with the same coverage are joined. the outline is in the coordinates of the
+// svg output, which translates the shapes by half a pixel, so that the pixel (x, y)
+// is the square from (x - 0.5, y - 0.5) to (x + 0.5, y + 0.5) of the outline
+pub fn rasterize_outline<T: Shape>(shape: &T, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+    lines.clear();
+    OUTLINE.with(|points| {
+        AREAS.with(|acc| {
+            let points = &mut *points.borrow_mut();
+            let acc = &mut *acc.borrow_mut();
+            points.clear();
+            shape.outline(points);
+            if points.len() < 3 {
+                return;
+            }
+            let (mut xmin, mut ymin) = (f64::MAX, f64::MAX);
+            let (mut xmax, mut ymax) = (f64::MIN, f64::MIN);
+            for p in points.iter_mut() {
+                p.0 += 0.5;
+                p.1 += 0.5;
+                xmin = xmin.min(p.0);
+                ymin = ymin.min(p.1);
+                xmax = xmax.max(p.0);
+                ymax = ymax.max(p.1);
+            }
+            // the bounding box in pixels, cropped to the image
+            let x0 = xmin.floor().max(0.0).min(w as f64) as u32;
+            let y0 = ymin.floor().max(0.0).min(h as f64) as u32;
+            let x1 = xmax.ceil().max(0.0).min(w as f64) as u32;
+            let y1 = ymax.ceil().max(0.0).min(h as f64) as u32;
+            if x0 >= x1 || y0 >= y1 {
+                return;
+            }
+            let bw = (x1 - x0) as usize;
+            let bh = (y1 - y0) as usize;
+            // two more columns for the deltas at the right edge of the box
+            let stride = bw + 2;
+            acc.clear();
+            acc.resize(stride * bh, 0.0);
+            let n = points.len();
+            for i in 0..n {
+                let (ax, ay) = points[i];
+                let (bx, by) = points[(i + 1) % n];
+                let a = (ax - x0 as f64, ay - y0 as f64);
+                let b = (bx - x0 as f64, by - y0 as f64);
+                accumulate_edge(acc, stride, bw as f64, bh, a, b);
+            }
+
+            for row in 0..bh {
+                let y = y0 + row as u32;
+                let mut area = 0.0;
+                for col in 0..bw {
+                    area += acc[row * stride + col];
+                    // the winding is summed, the pixels inside are covered once whatever
+                    // the direction of the outline
+                    let coverage = (area.abs().min(1.0) * 255.0 + 0.5) as u8;
+                    if coverage == 0 {
+                        continue;
+                    }
+                    let x = x0 + col as u32;
+                    match lines.last_mut() {
+                        Some(l) if l.y == y && l.x2 + 1 == x && l.coverage == coverage => l.x2 = x,
+                        _ => lines.push(Scanline {
+                            y,
+                            x1: x,
+                            x2: x,
+                            coverage,
+                        }),
+                    }
+                }
+            }
+        })
+    });
+}
+
+// append the outline of the ellipse at (cx, cy) with the radii rx and ry, rotated by
+// degree around its center like svg
+pub fn ellipse_outline(
+    cx: f64,
+    cy: f64,
+    rx: f64,
+    ry: f64,
+    degree: f64,
+    points: &mut Vec<(f64, f64)>,
+) {
+    // the segments are at most a tenth of a pixel inside the arcs of the largest radius
+    let n = (std::f64::consts::PI * (5.0 * rx.max(ry)).sqrt())
+        .ceil()
+        .max(8.0) as usize;
+    let (sin, cos) = degree.to_radians().sin_cos();
+    for i in 0..n {
+        let (st, ct) = (i as f64 * std::f64::consts::TAU / n as f64).sin_cos();
+        let (x, y) = (rx * ct, ry * st);
+        points.push((cx + x * cos - y * sin, cy + x * sin + y * cos));
+    }
+}
+
+// append the outline of the stroke of the quadratic bezier, width wide with butt caps
+// like svg
+pub fn quad_bezier_stroke_outline(
+    p0: (f64, f64),
+    p1: (f64, f64),
+    p2: (f64, f64),
+    width: f64,
+    points: &mut Vec<(f64, f64)>,
+) {
+    // the segments of the curve are at most a tenth of a pixel from it
+    let (ddx, ddy) = (p0.0 - 2.0 * p1.0 + p2.0, p0.1 - 2.0 * p1.1 + p2.1);
+    let n = ((ddx * ddx + ddy * ddy).sqrt() / 0.4)
+        .sqrt()
+        .ceil()
+        .max(1.0) as usize;
+    // the point of the curve at the ith step and the normal half the width long
+    let at = |i: usize| {
+        let t = i as f64 / n as f64;
+        let u = 1.0 - t;
+        let x = u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p2.0;
+        let y = u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p2.1;
+        let mut dx = u * (p1.0 - p0.0) + t * (p2.0 - p1.0);
+        let mut dy = u * (p1.1 - p0.1) + t * (p2.1 - p1.1);
+        if dx == 0.0 && dy == 0.0 {
+            // the tangent at an end on the control point
+            dx = p2.0 - p0.0;
+            dy = p2.1 - p0.1;
+        }
+        let len = (dx * dx + dy * dy).sqrt();
+        (x, y, -dy / len * width / 2.0, dx / len * width / 2.0)
+    };
+    // no valid quadratic has the same ends, see Quadratic::valid
+    if p0 == p2 {
+        return;
+    }
+    // the left side forward, then the right side backward
+    for i in 0..=n {
+        let (x, y, nx, ny) = at(i);
+        points.push((x + nx, y + ny));
+    }
+    for i in (0..=n).rev() {
+        let (x, y, nx, ny) = at(i);
+        points.push((x - nx, y - ny));
+    }
+}
+
+// add the edge from a to b to the area deltas, the parts left and right of the box are
+// moved onto its sides, where they still cover the pixels on their left or right
+fn accumulate_edge(acc: &mut [f32], stride: usize, w: f64, h: usize, a: (f64, f64), b: (f64, f64)) {
+    let mut ts = [0.0, 1.0, 1.0, 1.0];
+    let mut n = 1;
+    for side in [0.0, w] {
+        let t = (side - a.0) / (b.0 - a.0);
+        if t > 0.0 && t < 1.0 {
+            ts[n] = t;
+            n += 1;
+        }
+    }
+    ts[1..n].sort_unstable_by(|t1, t2| t1.partial_cmp(t2).unwrap());
+    ts[n] = 1.0;
+    let at = |t: f64| {
+        (
+            (a.0 + (b.0 - a.0) * t).max(0.0).min(w),
+            a.1 + (b.1 - a.1) * t,
+        )
+    };
+    for i in 0..n {
+        accumulate_line(acc, stride, w, h, at(ts[i]), at(ts[i + 1]));
+    }
+}
+
+// add the signed area between the line and the right side of the box to the deltas of
+// the rows it crosses, the line is inside the box horizontally
+fn accumulate_line(
+    acc: &mut [f32],
+    stride: usize,
+    w: f64,
+    h: usize,
+    p0: (f64, f64),
+    p1: (f64, f64),
+) {
+    if p0.1 == p1.1 {
+        return;
+    }
+    let (dir, p0, p1) = if p0.1 < p1.1 {
+        (1.0, p0, p1)
+    } else {
+        (-1.0, p1, p0)
+    };
+    let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+    let ystart = p0.1.max(0.0);
+    let yend = p1.1.min(h as f64);
+    if ystart >= yend {
+        return;
+    }
+    let mut x = p0.0 + (ystart - p0.1) * dxdy;
+    for y in ystart as usize..(yend.ceil() as usize) {
+        let row = &mut acc[y * stride..(y + 1) * stride];
+        let dy = yend.min(y as f64 + 1.0) - ystart.max(y as f64);
+        // rounded into the box
+        let xnext = (x + dxdy * dy).max(0.0).min(w);
+        let d = (dy * dir) as f32;
+        let (xl, xr) = if x < xnext { (x, xnext) } else { (xnext, x) };
+        let xlf = xl.floor();
+        let xli = xlf as usize;
+        let xmf = (0.5 * (x + xnext) - xlf) as f32;
+        if xr <= xlf + 1.0 {
+            // within a pixel, the part right of the line is covered
+            row[xli] += d - d * xmf;
+            row[xli + 1] += d * xmf;
+        } else {
+            let s = (1.0 / (xr - xl)) as f32;
+            let x0f = (xl - xlf) as f32;
+            let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+            let x1f = (xr - xr.ceil() + 1.0) as f32;
+            let am = 0.5 * s * x1f * x1f;
+            let xri = xr.ceil() as usize;
+            row[xli] += d * a0;
+            if xri == xli + 2 {
+                row[xli + 1] += d * (1.0 - a0 - am);
+            } else {
+                let a1 = s * (1.5 - x0f);
+                row[xli + 1] += d * (a1 - a0);
+                for a in &mut row[xli + 2..xri - 1] {
+                    *a += d * s;
+                }
+                let a2 = a1 + (xri - xli - 3) as f32 * s;
+                row[xri - 1] += d * (1.0 - a2 - am);
+            }
+            row[xri] += d * am;
+        }
+        x = xnext;
+    }
+}
+
+// rasterize polygon, the scanlines are appended to lines
 // points must be clockwise
-pub fn rasterize_polygon(points: &Vec<Point>, w: u32, h: u32) -> Vec<Scanline> {
+pub fn rasterize_polygon(points: &[Point], w: u32, h: u32, lines: &mut Vec<Scanline>) {
     if points.len() < 3 {
-        return Vec::new();
+        return;
     }
     // get y range
     let mut ymin = std::i32::MAX;
@@ -21,47 +276,37 @@ pub fn rasterize_polygon(points: &Vec<Point>, w: u32, h: u32) -> Vec<Scanline> {
     let range = (ymax - ymin) as usize;
 
     // init two y axis buffer
-    let mut buf_lhs: Vec<i32> = vec![std::i32::MAX; range + 1];
-    let mut buf_rhs: Vec<i32> = vec![std::i32::MIN; range + 1];
-
-    // scan each line
-    for i in 0..(points.len() - 1) {
-        let j = i + 1;
-        // Pi -> Pj
+    with_rows(range + 1, |buf_lhs, buf_rhs| {
+        // scan each line
+        for i in 0..(points.len() - 1) {
+            let j = i + 1;
+            // Pi -> Pj
+            rasterize_line(&points[i], &points[j], buf_lhs, buf_rhs, w, h, ymin);
+        }
         rasterize_line(
-            &points[i],
-            &points[j],
-            &mut buf_lhs,
-            &mut buf_rhs,
+            &points[points.len() - 1],
+            &points[0],
+            buf_lhs,
+            buf_rhs,
             w,
             h,
             ymin,
         );
-    }
-    rasterize_line(
-        &points[points.len() - 1],
-        &points[0],
-        &mut buf_lhs,
-        &mut buf_rhs,
-        w,
-        h,
-        ymin,
-    );
 
-    let mut scanlines = Vec::new();
-    for i in 0..=range {
-        let y = i as i32 + ymin;
-        if y >= 0 && y < h as i32 {
-            if buf_rhs[i] >= 0 {
-                scanlines.push(Scanline {
-                    y: clamp(y as u32, 0, h - 1),
-                    x1: clamp(buf_lhs[i], 0, w as i32 - 1) as u32,
-                    x2: clamp(buf_rhs[i], 0, w as i32 - 1) as u32,
-                });
+        for i in 0..=range {
+            let y = i as i32 + ymin;
+            if y >= 0 && y < h as i32 {
+                if buf_rhs[i] >= 0 {
+                    lines.push(Scanline {
+                        y: clamp(y as u32, 0, h - 1),
+                        x1: clamp(buf_lhs[i], 0, w as i32 - 1) as u32,
+                        x2: clamp(buf_rhs[i], 0, w as i32 - 1) as u32,
+                        coverage: 255,
+                    });
+                }
             }
         }
-    }
-    scanlines
+    });
 }
 
 pub fn rasterize_line(
@@ -189,6 +434,7 @@ pub fn rasterize_quad_bezier_seg(
                             y: clamp(y0, 0, h as i32 - 1) as u32,
                             x1: x as u32,
                             x2: x as u32,
+                            coverage: 255,
                         }
                     });
                 }
@@ -593,9 +839,8 @@ pub fn rasterize_quad_rational_bezier(
     /* remaining */
 }
 
-// Ellipse
-pub fn rasterize_ellipse(o: &Point, rx: u32, ry: u32) -> Vec<Scanline> {
-    let mut lines = Vec::new();
+// Ellipse, the scanlines are appended to lines
+pub fn rasterize_ellipse(o: &Point, rx: u32, ry: u32, lines: &mut Vec<Scanline>) {
     let mut x = -(rx as i32);
     let mut y = 0;
     let mut e2 = ry as i32;
@@ -612,12 +857,14 @@ pub fn rasterize_ellipse(o: &Point, rx: u32, ry: u32) -> Vec<Scanline> {
                 y: clamp_to_u32(o.y + y),
                 x1: clamp_to_u32(o.x + x),
                 x2: clamp_to_u32(o.x - x),
+                coverage: 255,
             });
             if y != 0 {
                 lines.push(Scanline {
                     y: clamp_to_u32(o.y - y),
                     x1: clamp_to_u32(o.x + x),
                     x2: clamp_to_u32(o.x - x),
+                    coverage: 255,
                 });
             }
         }
@@ -645,15 +892,16 @@ pub fn rasterize_ellipse(o: &Point, rx: u32, ry: u32) -> Vec<Scanline> {
             y: clamp_to_u32(o.y + y),
             x1: clamp_to_u32(o.x),
             x2: clamp_to_u32(o.x),
+            coverage: 255,
         });
         lines.push(Scanline {
             y: clamp_to_u32(o.y - y),
             x1: clamp_to_u32(o.x),
             x2: clamp_to_u32(o.x),
+            coverage: 255,
         });
         y += 1;
     }
-    lines
 }
 
 fn clamp_to_u32(n: i32) -> u32 {
@@ -668,7 +916,8 @@ pub fn rasterize_rotated_ellipse(
     angle: f64,
     w_: u32,
     h: u32,
-) -> Vec<Scanline> {
+    lines: &mut Vec<Scanline>,
+) {
     /* plot ellipse rotated by angle (radian) */
     let mut xd = (rx * rx) as f64;
     let mut yd = (ry * ry) as f64;
@@ -690,9 +939,12 @@ pub fn rasterize_rotated_ellipse(
         4.0 * zd * angle.cos(),
         w_,
         h,
+        lines,
     )
 }
 
+// the coordinates are passed apart like in the other bezier functions, plus the buffer
+#[allow(clippy::too_many_arguments)]
 pub fn rasterize_rotated_ellipse_rect(
     x0: i32,
     y0: i32,
@@ -701,7 +953,8 @@ pub fn rasterize_rotated_ellipse_rect(
     zd: f64,
     w_: u32,
     h: u32,
-) -> Vec<Scanline> {
+    lines: &mut Vec<Scanline>,
+) {
     let mut xd = (x1 - x0) as f64;
     let mut yd = (y1 - y0) as f64;
     let mut w = xd * yd;
@@ -711,7 +964,7 @@ pub fn rasterize_rotated_ellipse_rect(
     } /* squared weight of P1 */
 
     if !(w <= 1.0 && w >= 0.0) {
-        return Vec::new();
+        return;
     }
     assert!(w <= 1.0 && w >= 0.0); /* limit angle to |zd|<=xd*yd */
     xd = (xd * w + 0.5).floor();
@@ -720,78 +973,161 @@ pub fn rasterize_rotated_ellipse_rect(
     let range = (y1 - y0) as usize;
 
     // init two y axis buffer
-    let mut buf_lhs: Vec<i32> = vec![std::i32::MAX; range + 1];
-    let mut buf_rhs: Vec<i32> = vec![std::i32::MIN; range + 1];
-
-    rasterize_quad_rational_bezier_seg(
-        x0,
-        y0 + yd as i32,
-        x0,
-        y0,
-        x0 + xd as i32,
-        y0,
-        1.0 - w,
-        &mut buf_lhs,
-        &mut buf_rhs,
-        w_,
-        h,
-        y0,
-    );
-    rasterize_quad_rational_bezier_seg(
-        x0,
-        y0 + yd as i32,
-        x0,
-        y1,
-        x1 - xd as i32,
-        y1,
-        w,
-        &mut buf_lhs,
-        &mut buf_rhs,
-        w_,
-        h,
-        y0,
-    );
-    rasterize_quad_rational_bezier_seg(
-        x1,
-        y1 - yd as i32,
-        x1,
-        y1,
-        x1 - xd as i32,
-        y1,
-        1.0 - w,
-        &mut buf_lhs,
-        &mut buf_rhs,
-        w_,
-        h,
-        y0,
-    );
-    rasterize_quad_rational_bezier_seg(
-        x1,
-        y1 - yd as i32,
-        x1,
-        y0,
-        x0 + xd as i32,
-        y0,
-        w,
-        &mut buf_lhs,
-        &mut buf_rhs,
-        w_,
-        h,
-        y0,
-    );
+    with_rows(range + 1, |buf_lhs, buf_rhs| {
+        rasterize_quad_rational_bezier_seg(
+            x0,
+            y0 + yd as i32,
+            x0,
+            y0,
+            x0 + xd as i32,
+            y0,
+            1.0 - w,
+            buf_lhs,
+            buf_rhs,
+            w_,
+            h,
+            y0,
+        );
+        rasterize_quad_rational_bezier_seg(
+            x0,
+            y0 + yd as i32,
+            x0,
+            y1,
+            x1 - xd as i32,
+            y1,
+            w,
+            buf_lhs,
+            buf_rhs,
+            w_,
+            h,
+            y0,
+        );
+        rasterize_quad_rational_bezier_seg(
+            x1,
+            y1 - yd as i32,
+            x1,
+            y1,
+            x1 - xd as i32,
+            y1,
+            1.0 - w,
+            buf_lhs,
+            buf_rhs,
+            w_,
+            h,
+            y0,
+        );
+        rasterize_quad_rational_bezier_seg(
+            x1,
+            y1 - yd as i32,
+            x1,
+            y0,
+            x0 + xd as i32,
+            y0,
+            w,
+            buf_lhs,
+            buf_rhs,
+            w_,
+            h,
+            y0,
+        );
 
-    let mut scanlines = Vec::new();
-    for i in 0..=range {
-        let y = i as i32 + y0;
-        if y >= 0 && y < h as i32 {
-            if buf_rhs[i] >= 0 {
-                scanlines.push(Scanline {
-                    y: clamp(y as u32, 0, h - 1),
-                    x1: clamp(buf_lhs[i], 0, w_ as i32 - 1) as u32,
-                    x2: clamp(buf_rhs[i], 0, w_ as i32 - 1) as u32,
-                });
+        for i in 0..=range {
+            let y = i as i32 + y0;
+            if y >= 0 && y < h as i32 {
+                if buf_rhs[i] >= 0 {
+                    lines.push(Scanline {
+                        y: clamp(y as u32, 0, h - 1),
+                        x1: clamp(buf_lhs[i], 0, w_ as i32 - 1) as u32,
+                        x2: clamp(buf_rhs[i], 0, w_ as i32 - 1) as u32,
+                        coverage: 255,
+                    });
+                }
             }
         }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{Ellipse, Quadratic, Rectangle, Triangle};
+
+    fn covered_area(lines: &[Scanline]) -> f64 {
+        lines
+            .iter()
+            .map(|l| (l.x2 - l.x1 + 1) as f64 * l.coverage as f64 / 255.0)
+            .sum()
+    }
+
+    #[test]
+    fn test_rasterize_outline() {
+        // the svg rect from (2.5, 2.5) to (5.5, 5.5) halves the pixels of its edges
+        let r = Rectangle {
+            p: Point { x: 2, y: 2 },
+            x: 3,
+            y: 3,
+        };
+        let mut lines = Vec::new();
+        rasterize_outline(&r, 10, 10, &mut lines);
+        let runs: Vec<(u32, u32, u32, u8)> = lines
+            .iter()
+            .map(|l| (l.y, l.x1, l.x2, l.coverage))
+            .collect();
+        let mut expected = vec![(2, 2, 2, 64), (2, 3, 4, 128), (2, 5, 5, 64)];
+        for y in 3..5 {
+            expected.extend_from_slice(&[(y, 2, 2, 128), (y, 3, 4, 255), (y, 5, 5, 128)]);
+        }
+        expected.extend_from_slice(&[(5, 2, 2, 64), (5, 3, 4, 128), (5, 5, 5, 64)]);
+        assert_eq!(runs, expected);
+
+        // the first row is covered too and the parts out of the image are cropped
+        let t = Triangle {
+            a: Point { x: -10, y: -10 },
+            b: Point { x: 9, y: -10 },
+            c: Point { x: 9, y: 9 },
+        };
+        rasterize_outline(&t, 10, 10, &mut lines);
+        assert!(lines.iter().all(|l| l.x2 < 10 && l.y < 10));
+        assert!(lines
+            .iter()
+            .any(|l| (l.y, l.x1, l.x2, l.coverage) == (0, 1, 8, 255)));
+        // the pixels along the diagonal are half covered
+        assert!(lines
+            .iter()
+            .any(|l| (l.y, l.x1, l.x2, l.coverage) == (5, 5, 5, 128)));
+        // the right edge is at 9.5 in the pixels
+        assert!((covered_area(&lines) - 9.5 * 9.5 / 2.0).abs() < 0.1);
+
+        // the area of the outline, up to the rounding of the coverage
+        let e = Ellipse {
+            o: Point { x: 50, y: 50 },
+            rx: 30,
+            ry: 20,
+        };
+        rasterize_outline(&e, 100, 100, &mut lines);
+        let area = std::f64::consts::PI * 30.0 * 20.0;
+        assert!((covered_area(&lines) - area).abs() < area * 0.005);
+
+        // the stroke is half a pixel wide
+        let q = Quadratic {
+            p0: Point { x: 10, y: 10 },
+            p1: Point { x: 50, y: 90 },
+            p2: Point { x: 90, y: 10 },
+        };
+        rasterize_outline(&q, 100, 100, &mut lines);
+        assert!(lines.iter().all(|l| l.coverage < 255));
+        let mut length = 0.0;
+        let mut prev = (10.0, 10.0);
+        for i in 1..=1000 {
+            let t = i as f64 / 1000.0;
+            let u = 1.0 - t;
+            let p = (
+                u * u * 10.0 + 2.0 * u * t * 50.0 + t * t * 90.0,
+                u * u * 10.0 + 2.0 * u * t * 90.0 + t * t * 10.0,
+            );
+            length += ((p.0 - prev.0).powi(2) + (p.1 - prev.1).powi(2)).sqrt();
+            prev = p;
+        }
+        assert!((covered_area(&lines) - length * 0.5).abs() < length * 0.5 * 0.02);
     }
-    scanlines
 }
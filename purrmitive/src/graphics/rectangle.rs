@@ -58,9 +58,13 @@ impl Rectangle {
 }
 
 impl Shape for Rectangle {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
-        let px = rng.gen_range(0, w as i32);
-        let py = rng.gen_range(0, h as i32);
+    fn random_at<T: SeedableRng + RngCore>(
+        px: i32,
+        py: i32,
+        _w: u32,
+        _h: u32,
+        rng: &mut T,
+    ) -> Self {
         let x = rng.gen_range(0, 32) + 1;
         let y = rng.gen_range(0, 32) + 1;
 
@@ -75,8 +79,9 @@ impl Shape for Rectangle {
         self.do_mutate(w, h, r, rng);
     }
 
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
-        let points = vec![
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
+        let points = [
             self.p,
             Point {
                 x: self.p.x + self.x as i32,
@@ -91,7 +96,7 @@ impl Shape for Rectangle {
                 y: self.p.y + self.y as i32,
             },
         ];
-        rasterize_polygon(&points, w, h)
+        rasterize_polygon(&points, w, h, lines);
     }
 
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
@@ -102,6 +107,20 @@ impl Shape for Rectangle {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        Rectangle {
+            p: self.p.transform(sx, sy),
+            x: scale_length(self.x, sx),
+            y: scale_length(self.y, sy),
+        }
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        let (x, y) = (self.p.x as f64, self.p.y as f64);
+        let (w, h) = (self.x as f64, self.y as f64);
+        points.extend_from_slice(&[(x, y), (x + w, y), (x + w, y + h), (x, y + h)]);
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         format!(
             "<rect {} x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" />",
@@ -128,10 +147,10 @@ impl Default for RotatedRectangle {
 }
 
 impl Shape for RotatedRectangle {
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
+    fn random_at<T: SeedableRng + RngCore>(x: i32, y: i32, w: u32, h: u32, rng: &mut T) -> Self {
         RotatedRectangle {
             degree: rng.gen_range(0, 360),
-            rect: Rectangle::random(w, h, rng),
+            rect: Rectangle::random_at(x, y, w, h, rng),
         }
     }
     fn mutate<T: SeedableRng + RngCore>(&mut self, w: u32, h: u32, rng: &mut T) {
@@ -149,7 +168,8 @@ impl Shape for RotatedRectangle {
             _ => unreachable!(),
         }
     }
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
         let c = Point {
             x: self.rect.p.x + self.rect.x as i32 / 2,
             y: self.rect.p.y + self.rect.y as i32 / 2,
@@ -171,16 +191,8 @@ impl Shape for RotatedRectangle {
         rotate_point(&c, &mut p1, self.degree as f32);
         rotate_point(&c, &mut p2, self.degree as f32);
         rotate_point(&c, &mut p3, self.degree as f32);
-        let points = vec![p0, p1, p2, p3];
-        let lines = rasterize_polygon(&points, w, h);
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+        rasterize_polygon(&[p0, p1, p2, p3], w, h, lines);
+        crop_lines(lines, w, h);
     }
     fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
         let (w, h) = img.dimensions();
@@ -189,6 +201,33 @@ impl Shape for RotatedRectangle {
             line.draw(img, &color);
         }
     }
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        let s = (sx + sy) / 2.0;
+        RotatedRectangle {
+            degree: self.degree,
+            rect: Rectangle {
+                p: self.rect.p.transform(sx, sy),
+                x: scale_length(self.rect.x, s),
+                y: scale_length(self.rect.y, s),
+            },
+        }
+    }
+
+    // the unit square scaled, rotated around the rounded down middle and translated,
+    // like the transform of to_svg
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        let (w, h) = (self.rect.x as f64, self.rect.y as f64);
+        let (cx, cy) = ((self.rect.x / 2) as f64, (self.rect.y / 2) as f64);
+        let (sin, cos) = (self.degree as f64).to_radians().sin_cos();
+        for &(x, y) in &[(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)] {
+            let (dx, dy) = (x - cx, y - cy);
+            points.push((
+                self.rect.p.x as f64 + cx + dx * cos - dy * sin,
+                self.rect.p.y as f64 + cy + dx * sin + dy * cos,
+            ));
+        }
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         format!(
             "<g transform=\"translate({} {}) rotate({} {} {}) scale({} {})\"><rect {} x=\"0\" y=\"0\" width=\"1\" height=\"1\" /></g>",
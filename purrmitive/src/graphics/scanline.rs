@@ -1,27 +1,62 @@
-use crate::graphics::Point;
-use crate::{alpha_compose, clamp, Rgba, RgbaImage};
+use crate::graphics::Blend;
+use crate::{clamp, compose_srgb, div255, Rgba, RgbaImage};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Scanline {
     pub y: u32,
     pub x1: u32,
     pub x2: u32,
+    // how much of each pixel the shape covers, 255 is the whole pixel
+    pub coverage: u8,
 }
 
 impl Scanline {
+    // color with the alpha scaled by the coverage
+    #[inline]
+    pub fn covered(&self, color: &Rgba<u8>) -> Rgba<u8> {
+        if self.coverage == 255 {
+            return *color;
+        }
+        let a = div255(color.0[3] as u32 * self.coverage as u32);
+        Rgba([color.0[0], color.0[1], color.0[2], a as u8])
+    }
+
     pub fn draw(&self, img: &mut RgbaImage, color: &Rgba<u8>) {
         assert!(self.x1 <= self.x2);
+        let color = self.covered(color);
         for x in self.x1..=self.x2 {
             let pixel: &mut Rgba<u8> = img.get_pixel_mut(x as u32, self.y as u32);
             // (foreground.r * alpha) + (background.r * (1.0 - alpha));
-            let c = alpha_compose(pixel, color);
+            let c = compose_srgb(pixel, &color);
             pixel.0 = c.0;
         }
     }
 
+    pub fn draw_with(&self, img: &mut RgbaImage, color: &Rgba<u8>, blend: &Blend) {
+        assert!(self.x1 <= self.x2);
+        let color = &self.covered(color);
+        for x in self.x1..=self.x2 {
+            let pixel: &mut Rgba<u8> = img.get_pixel_mut(x, self.y);
+            *pixel = blend.compose(pixel, color);
+        }
+    }
+
     pub fn crop(&mut self, w: u32, h: u32) {
         self.y = clamp(self.y, 0, h - 1);
         self.x1 = clamp(self.x1, 0, w - 1);
         self.x2 = clamp(self.x2, 0, w - 1);
     }
 }
+
+// keep the visible lines, cropped to the image
+pub fn crop_lines(lines: &mut Vec<Scanline>, w: u32, h: u32) {
+    lines.retain(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h);
+    for line in lines.iter_mut() {
+        line.crop(w, h);
+    }
+}
+
+// whether some pixels of the lines are partly covered
+pub fn has_partial_coverage(lines: &[Scanline]) -> bool {
+    lines.iter().any(|l| l.coverage < 255)
+}
@@ -75,22 +75,13 @@ impl Shape for Triangle {
             line.draw(img, &color);
         }
     }
-    fn rasterize(&self, w: u32, h: u32) -> Vec<Scanline> {
+    fn rasterize_into(&self, w: u32, h: u32, lines: &mut Vec<Scanline>) {
+        lines.clear();
         // clockwise this
-        let points = Vec::from(self.clockwise());
-        let lines = rasterize_polygon(&points, w, h);
-        let mut visible_lines: Vec<Scanline> = lines
-            .into_iter()
-            .filter(|l| l.x1 <= l.x2 && l.x2 > 0 && l.x1 < w && l.y > 0 && l.y < h)
-            .collect();
-        for line in &mut visible_lines {
-            line.crop(w, h);
-        }
-        visible_lines
+        rasterize_polygon(&self.clockwise(), w, h, lines);
+        crop_lines(lines, w, h);
     }
-    fn random<T: SeedableRng + RngCore>(w: u32, h: u32, rng: &mut T) -> Self {
-        let x1 = rng.gen_range(0, w as i32);
-        let y1 = rng.gen_range(0, h as i32);
+    fn random_at<T: SeedableRng + RngCore>(x1: i32, y1: i32, w: u32, h: u32, rng: &mut T) -> Self {
         let x2 = x1 + rng.gen_range(0, 31) - 15;
         let y2 = y1 + rng.gen_range(0, 31) - 15;
         let x3 = x1 + rng.gen_range(0, 31) - 15;
@@ -153,6 +144,20 @@ impl Shape for Triangle {
         }
     }
 
+    fn transform(&self, sx: f64, sy: f64) -> Self {
+        Triangle {
+            a: self.a.transform(sx, sy),
+            b: self.b.transform(sx, sy),
+            c: self.c.transform(sx, sy),
+        }
+    }
+
+    fn outline(&self, points: &mut Vec<(f64, f64)>) {
+        for p in &[self.a, self.b, self.c] {
+            points.push((p.x as f64, p.y as f64));
+        }
+    }
+
     fn to_svg(&self, attr: &str) -> String {
         format!(
             "<polygon {} points=\"{},{} {},{} {},{}\" />",
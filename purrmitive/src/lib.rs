@@ -17,6 +17,12 @@ fn degrees(radians: f64) -> f64 {
     return radians * 180.0 / std::f64::consts::PI;
 }
 
+// x / 255 rounded down, exact for x <= 255 * 255
+#[inline]
+fn div255(x: u32) -> u32 {
+    (x + 1 + (x >> 8)) >> 8
+}
+
 pub fn alpha_compose(bg: &Rgba<u8>, fg: &Rgba<u8>) -> Rgba<u8> {
     let alpha_f = fg.0[3] as f64 / 255.0;
     let r = (bg.0[0] as f64 * (1.0 - alpha_f) + fg.0[0] as f64 * alpha_f) as u8;
@@ -24,3 +30,52 @@ pub fn alpha_compose(bg: &Rgba<u8>, fg: &Rgba<u8>) -> Rgba<u8> {
     let b = (bg.0[2] as f64 * (1.0 - alpha_f) + fg.0[2] as f64 * alpha_f) as u8;
     Rgba([r, g, b, 255])
 }
+
+// alpha_compose in integer fixed point, so that the simd paths give the same results.
+// the exact quotient is rounded down, where alpha_compose may be 1 less because of the
+// rounding of the f64 product
+#[cfg(feature = "simd")]
+pub fn alpha_compose_fixed(bg: &Rgba<u8>, fg: &Rgba<u8>) -> Rgba<u8> {
+    let a = fg.0[3] as u32;
+    let blend = |b: u8, f: u8| div255(b as u32 * (255 - a) + f as u32 * a) as u8;
+    Rgba([
+        blend(bg.0[0], fg.0[0]),
+        blend(bg.0[1], fg.0[1]),
+        blend(bg.0[2], fg.0[2]),
+        255,
+    ])
+}
+
+// the srgb compositing of the shapes, in fixed point with the simd feature so that
+// the images are drawn like they are scored
+#[inline]
+fn compose_srgb(bg: &Rgba<u8>, fg: &Rgba<u8>) -> Rgba<u8> {
+    #[cfg(feature = "simd")]
+    return alpha_compose_fixed(bg, fg);
+    #[cfg(not(feature = "simd"))]
+    alpha_compose(bg, fg)
+}
+
+#[cfg(all(test, feature = "simd"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alpha_compose_fixed() {
+        let mut differ = 0;
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                for f in 0..=255u8 {
+                    let bg = Rgba([b, b, b, 255]);
+                    let fg = Rgba([f, f, f, a]);
+                    let fixed = alpha_compose_fixed(&bg, &fg).0[0];
+                    let reference = alpha_compose(&bg, &fg).0[0];
+                    // the same, or the reference is rounded 1 lower
+                    assert!(fixed == reference || fixed == reference + 1);
+                    differ += (fixed != reference) as u32;
+                }
+            }
+        }
+        assert!(differ < 1 << 14);
+    }
+}